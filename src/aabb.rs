@@ -0,0 +1,71 @@
+use crate::point3d::*;
+use crate::ray::*;
+
+// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    min: Point3D,
+    max: Point3D,
+}
+
+impl Aabb {
+    pub fn new(a: Point3D, b: Point3D) -> Aabb {
+        // Treat the two points as opposite corners, in any order.
+        Aabb {
+            min: Point3D::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Point3D::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn empty() -> Aabb {
+        let inf = f64::INFINITY;
+        Aabb { min: Point3D::new(inf, inf, inf), max: Point3D::new(-inf, -inf, -inf) }
+    }
+
+    pub fn universe() -> Aabb {
+        let inf = f64::INFINITY;
+        Aabb { min: Point3D::new(-inf, -inf, -inf), max: Point3D::new(inf, inf, inf) }
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3D::new(self.min.x().min(other.min.x()), self.min.y().min(other.min.y()), self.min.z().min(other.min.z())),
+            max: Point3D::new(self.max.x().max(other.max.x()), self.max.y().max(other.max.y()), self.max.z().max(other.max.z())),
+        }
+    }
+
+    pub fn overlap(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3D::new(self.min.x().max(other.min.x()), self.min.y().max(other.min.y()), self.min.z().max(other.min.z())),
+            max: Point3D::new(self.max.x().min(other.max.x()), self.max.y().min(other.max.y()), self.max.z().min(other.max.z())),
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> bool {
+        self.hit_interval(r, ray_tmin, ray_tmax).is_some()
+    }
+
+    // The part of (ray_tmin, ray_tmax) during which the ray is inside the box.
+    pub fn hit_interval(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<(f64, f64)> {
+        // Slab test: clip the ray interval against each pair of axis-aligned planes.
+        let axes = [
+            (r.origin().x(), r.direction().x(), self.min.x(), self.max.x()),
+            (r.origin().y(), r.direction().y(), self.min.y(), self.max.y()),
+            (r.origin().z(), r.direction().z(), self.min.z(), self.max.z()),
+        ];
+        let mut tmin = ray_tmin;
+        let mut tmax = ray_tmax;
+        for (origin, direction, min, max) in axes.iter() {
+            let inv_d = 1.0 / direction;
+            let t0 = (min - origin) * inv_d;
+            let t1 = (max - origin) * inv_d;
+            let (t0, t1) = if inv_d < 0.0 { (t1, t0) } else { (t0, t1) };
+            tmin = if t0 > tmin { t0 } else { tmin };
+            tmax = if t1 < tmax { t1 } else { tmax };
+            if tmax <= tmin {
+                return None;
+            }
+        }
+        Some((tmin, tmax))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::camera::*;
+use rayon::prelude::*;
+use crate::random::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Arbitrary output variables: properties of the first surface seen through each pixel center,
+// written as separate layers next to the rendered image for compositing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    Depth, // Distance from the camera along its view direction, infinite where nothing is hit
+    Position, // World position
+    Normal, // Shading normal, facing the camera
+    Albedo,
+    MaterialId, // Hash of the material's index among the distinct ones in the world, 0 for the background
+    ObjectId, // One-based position in the world list of the object hit, 0 for the background
+    Uv, // Surface coordinates, in the first two channels
+}
+
+impl Aov {
+    pub fn all() -> Vec<Aov> {
+        vec![Aov::Depth, Aov::Position, Aov::Normal, Aov::Albedo, Aov::MaterialId, Aov::ObjectId, Aov::Uv]
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::all().into_iter().find(|aov| aov.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Uv => "uv",
+        }
+    }
+
+    // Number of meaningful channels: one for scalars, three for vectors and colors.
+    pub fn channels(&self) -> usize {
+        match self {
+            Aov::Depth | Aov::MaterialId | Aov::ObjectId => 1,
+            _ => 3,
+        }
+    }
+
+    // Value of the layer for a camera ray, and its first hit if any. Scalars go in x. Materials
+    // are told apart by their position among the distinct materials of the world, in materials,
+    // so that equal materials on different objects share an ID.
+    pub fn value(&self, camera: &Camera, materials: &[String], r: &Ray, rec: Option<&HitRecord>) -> Point3D {
+        let zero = Point3D::new(0.0, 0.0, 0.0);
+        let rec = match (self, rec) {
+            (Aov::Depth, None) => return Point3D::new(f64::INFINITY, 0.0, 0.0),
+            (_, None) => return zero,
+            (_, Some(rec)) => rec,
+        };
+        match self {
+            Aov::Depth => Point3D::new((rec.p - r.origin()).dot(&camera.forward()), 0.0, 0.0),
+            Aov::Position => rec.p,
+            Aov::Normal => rec.normal,
+            Aov::Albedo => rec.material.albedo(rec),
+            Aov::MaterialId => {
+                // Spread the indices over 24 bits, which survive being stored as a 32-bit float,
+                // so that neighbouring materials get far apart IDs.
+                let material = format!("{:?}", rec.material);
+                match materials.iter().position(|known| *known == material) {
+                    Some(index) => Point3D::new((hash_f64(&[index as f64]) * (1 << 24) as f64).floor().max(1.0), 0.0, 0.0),
+                    None => zero,
+                }
+            }
+            Aov::ObjectId => Point3D::new(rec.object_id as f64, 0.0, 0.0),
+            Aov::Uv => Point3D::new(rec.u, rec.v, 0.0),
+        }
+    }
+}
+
+// One row-major layer per AOV, from a ray through the center of each pixel.
+pub fn render_aovs(camera: &Camera, world: &dyn Hittable, aovs: &[Aov]) -> Vec<Vec<Point3D>> {
+    if aovs.is_empty() {
+        return Vec::new();
+    }
+    let stats = camera.stats();
+    let (width, height) = (stats.width() as usize, stats.height() as usize);
+    let mut all_materials = Vec::new();
+    world.collect_materials(&mut all_materials);
+    let mut materials: Vec<String> = Vec::new();
+    for material in all_materials.iter().map(|material| format!("{:?}", material)) {
+        if !materials.contains(&material) {
+            materials.push(material);
+        }
+    }
+    let pixels: Vec<Vec<Point3D>> = (0..width * height).into_par_iter().map(|index| {
+        let r = camera.get_center_ray((index % width) as f64, (index / width) as f64);
+        let rec = world.hit(&r, 0.001, f64::MAX);
+        aovs.iter().map(|aov| aov.value(camera, &materials, &r, rec.as_ref())).collect()
+    }).collect();
+    (0..aovs.len()).map(|layer| pixels.iter().map(|pixel| pixel[layer]).collect()).collect()
+}
+
+// Writes a layer as a Portable Float Map, grayscale from x when channels is 1. PFM stores
+// little-endian 32-bit floats, bottom row first.
+pub fn write_pfm(path: &Path, width: usize, height: usize, channels: usize, pixels: &[Point3D]) -> io::Result<()> {
+    let mut data = format!("{}\n{} {}\n-1.0\n", if channels == 1 { "Pf" } else { "PF" }, width, height).into_bytes();
+    for row in pixels.chunks(width).rev() {
+        for pixel in row.iter() {
+            let values = [pixel.x(), pixel.y(), pixel.z()];
+            for value in values.iter().take(channels) {
+                data.extend_from_slice(&(*value as f32).to_le_bytes());
+            }
+        }
+    }
+    fs::write(path, data)
+}
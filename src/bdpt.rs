@@ -0,0 +1,491 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::integrator::*;
+use crate::film::*;
+use crate::onb::*;
+use crate::random::*;
+use crate::spectrum::*;
+use crate::subsurface::*;
+use std::f64::consts::PI;
+
+// Bidirectional path tracing (Veach, chapter 10). Each camera sample traces one subpath from the
+// camera and one from a light, then joins every prefix of one to every prefix of the other. The
+// many ways of building the same path are combined with multiple importance sampling, so that
+// light focused by specular surfaces (caustics) is found by the strategies that trace it from the
+// light, including light tracing, which connects light subpaths straight to the lens and splats
+// them onto whichever pixel they land in.
+//
+// Only emissive objects are sampled as lights; light from the background is gathered only by
+// camera subpaths escaping the scene.
+pub struct BdptIntegrator;
+
+// What the subpaths are traced through and connected with.
+struct Scene<'a> {
+    camera: &'a Camera,
+    world: &'a dyn Hittable,
+    lights: &'a LightList<'a>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3D,
+    normal: Point3D, // Faces the side the subpath arrived from, or out of the light or camera
+    rec: Option<HitRecord>, // Set for light and surface vertices
+    beta: Point3D, // Throughput of the subpath up to this vertex
+    pdf_fwd: f64, // Area density of sampling this vertex from the previous one of its subpath
+    pdf_rev: f64, // Area density of sampling this vertex from the next one, walking the other way
+    delta: bool, // Scattered with a specular lobe, so no connection can be made to it
+    hero_only: bool, // Its subpath dropped the secondary wavelengths, weighing the hero thrice
+}
+
+impl Vertex {
+    fn camera(p: Point3D, forward: Point3D, beta: Point3D) -> Vertex {
+        Vertex { kind: VertexKind::Camera, p, normal: forward, rec: None, beta, pdf_fwd: 0.0, pdf_rev: 0.0, delta: false, hero_only: false }
+    }
+
+    // A point on a light, picked with area density pdf.
+    fn light(rec: HitRecord, pdf: f64) -> Vertex {
+        let beta = Point3D::new(1.0, 1.0, 1.0) / pdf;
+        Vertex { kind: VertexKind::Light, p: rec.p, normal: rec.normal, rec: Some(rec), beta, pdf_fwd: pdf, pdf_rev: 0.0, delta: false, hero_only: false }
+    }
+
+    fn surface(rec: HitRecord, beta: Point3D) -> Vertex {
+        Vertex { kind: VertexKind::Surface, p: rec.p, normal: rec.normal, rec: Some(rec), beta, pdf_fwd: 0.0, pdf_rev: 0.0, delta: false, hero_only: secondary_terminated() }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.rec.as_ref().is_some_and(|rec| rec.material.is_emissive())
+    }
+
+    // Scattered inside a translucent object, on a walk only camera subpaths take.
+    fn in_medium(&self) -> bool {
+        self.rec.as_ref().is_some_and(|rec| matches!(rec.material, Material::Medium(_)))
+    }
+
+    // Unit vector from this vertex towards p.
+    fn towards(&self, p: &Point3D) -> Point3D {
+        (*p - self.p).unit_vector()
+    }
+
+    // Light emitted from this vertex towards p.
+    fn emitted(&self, p: &Point3D) -> Point3D {
+        match &self.rec {
+            Some(rec) => {
+                let direction = self.towards(p);
+                let outward = if rec.front_face { rec.normal } else { -rec.normal };
+                let mut rec = rec.clone();
+                rec.set_face_normal(&Ray::new(*p, -direction), outward);
+                Scatterable::emitted(&rec.material, &Ray::new(*p, -direction), &rec)
+            }
+            None => Point3D::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Light scattered at this vertex from prev towards next, or emitted towards next at a light.
+    // The materials here scatter light the same way in both directions, so this holds for camera
+    // and light subpaths alike.
+    fn f(&self, prev: Option<&Vertex>, next: &Vertex) -> Point3D {
+        match (self.kind, &self.rec, prev) {
+            (VertexKind::Light, _, _) => self.emitted(&next.p),
+            (VertexKind::Surface, Some(rec), Some(prev)) => {
+                Scatterable::eval(&rec.material, rec, &self.towards(&next.p), &self.towards(&prev.p))
+            }
+            _ => Point3D::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Turns a solid angle density of sampling next from this vertex into an area density at next.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let to_next = next.p - self.p;
+        let mut pdf = pdf / to_next.length_squared();
+        if next.kind != VertexKind::Camera {
+            pdf *= next.normal.dot(&to_next.unit_vector()).abs();
+        }
+        pdf
+    }
+
+    // Area density of sampling next from this vertex, having arrived from prev.
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf = match (self.kind, &self.rec, prev) {
+            (VertexKind::Camera, _, _) => camera.pdf_direction(&self.p, &(next.p - self.p)),
+            (VertexKind::Light, _, _) => return self.pdf_light(next),
+            (VertexKind::Surface, Some(rec), Some(prev)) => {
+                Scatterable::pdf(&rec.material, rec, &self.towards(&next.p), &self.towards(&prev.p))
+            }
+            _ => 0.0,
+        };
+        self.convert_density(pdf, next)
+    }
+
+    // Area density of a light subpath starting at this point of a light going on to next.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        // Light subpaths leave lights in cosine-weighted directions.
+        let cos_theta = self.normal.dot(&self.towards(&next.p)).abs();
+        self.convert_density(cos_theta / PI, next)
+    }
+}
+
+// A path joined from a light and a camera subpath.
+struct Connection {
+    contribution: Point3D, // Light it carries, before MIS
+    sampled: Option<Vertex>, // Light or lens vertex sampled to make the connection, if any
+    raster: Option<(f64, f64)>, // Pixel to splat onto, when the path does not reach the camera through the current one
+}
+
+impl SampleIntegrator for BdptIntegrator {
+    fn li(&self, r: &Ray, camera: &Camera, world: &dyn Hittable, lights: &LightList, film: &Film) -> Point3D {
+        let scene = Scene { camera, world, lights };
+        let max_depth = camera.stats().max_depth();
+        let (camera_path, mut radiance) = self.camera_subpath(&scene, r, max_depth + 1);
+        restore_secondary();
+        let light_path = self.light_subpath(&scene, max_depth);
+
+        // A path of s light and t camera vertices bounces s + t - 2 times.
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || s + t > max_depth + 1 || (s == 1 && t == 1) {
+                    continue;
+                }
+                if let Some((contribution, raster)) = self.connect(&scene, &light_path, &camera_path, s, t) {
+                    match raster {
+                        Some((x, y)) => film.add_splat(x, y, to_rgb(contribution)),
+                        None => radiance = radiance + contribution,
+                    }
+                }
+            }
+        }
+        radiance
+    }
+
+    fn supports_spectral(&self) -> bool {
+        true
+    }
+}
+
+impl Scene<'_> {
+    fn visible(&self, a: &Point3D, b: &Point3D) -> bool {
+        let to_b = *b - *a;
+        let distance = to_b.length();
+        self.world.hit(&Ray::new(*a, to_b / distance), 0.001, distance - 0.001).is_none()
+    }
+}
+
+impl BdptIntegrator {
+    // Returns the subpath and the light its last ray found on escaping the scene, which no
+    // other strategy can sample.
+    fn camera_subpath(&self, scene: &Scene, r: &Ray, max_vertices: usize) -> (Vec<Vertex>, Point3D) {
+        let mut path = vec![Vertex::camera(r.origin(), scene.camera.forward(), Point3D::new(1.0, 1.0, 1.0))];
+        // The camera's importance over its sampling density is one for every camera ray.
+        let pdf_direction = scene.camera.pdf_direction(&r.origin(), &r.direction());
+        let escaped = self.random_walk(scene, r, Point3D::new(1.0, 1.0, 1.0), pdf_direction, max_vertices, &mut path);
+        (path, escaped)
+    }
+
+    fn light_subpath(&self, scene: &Scene, max_vertices: usize) -> Vec<Vertex> {
+        let (rec, pdf_position) = match scene.lights.sample_surface() {
+            Some(sample) => sample,
+            None => return Vec::new(),
+        };
+        let (p, normal) = (rec.p, rec.normal);
+        let light = Vertex::light(rec, pdf_position);
+        let direction = Onb::new(&normal).transform(&Point3D::random_cosine_direction());
+        let pdf_direction = normal.dot(&direction.unit_vector()) / PI;
+        let emitted = light.emitted(&(p + direction));
+        let beta = emitted * (light.beta * (normal.dot(&direction.unit_vector()) / pdf_direction));
+        let mut path = vec![light];
+        if max_vertices <= 1 || pdf_direction <= 0.0 {
+            return path;
+        }
+        self.random_walk(scene, &Ray::new(p, direction), beta, pdf_direction, max_vertices, &mut path);
+        path
+    }
+
+    // Extends path from its last vertex along r, which was sampled with solid angle density pdf,
+    // until it has max_vertices vertices, a path leaves the scene, is absorbed or is ended by
+    // Russian roulette. Returns the light found if it leaves the scene.
+    fn random_walk(&self, scene: &Scene, r: &Ray, beta: Point3D, pdf: f64, max_vertices: usize, path: &mut Vec<Vertex>) -> Point3D {
+        let mut ray = *r;
+        let mut walk: Option<Walk> = None; // Through a translucent object, when the path is on one
+        let mut beta = beta;
+        let mut pdf_fwd = pdf;
+        while path.len() < max_vertices {
+            let rec = match hit_on_walk(scene.world, &ray, walk.as_ref(), 0.001, f64::MAX) {
+                Some(rec) => rec,
+                None => {
+                    if path[0].kind == VertexKind::Camera {
+                        return beta * scene.camera.background_color(&ray);
+                    }
+                    break;
+                }
+            };
+            // Walking back through a medium has a different density than walking forwards,
+            // which the ratios of densities weighing the strategies cannot express.
+            if path[0].kind == VertexKind::Light && matches!(rec.material, Material::Medium(_)) {
+                break;
+            }
+            let prev = path.len() - 1;
+            let mut vertex = Vertex::surface(rec.clone(), beta);
+            vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let sample = match Scatterable::scatter(&rec.material, &ray, &rec) {
+                Some(sample) => sample,
+                None => break,
+            };
+            let wo = -ray.direction().unit_vector();
+            let wi = sample.direction.unit_vector();
+            beta = beta * sample.weight(&rec.normal);
+            pdf_fwd = sample.pdf;
+            // Density of sampling the way back, as if the path had come from the other end.
+            let mut pdf_rev = Scatterable::pdf(&rec.material, &rec, &wo, &wi);
+            if sample.specular {
+                let current = path.len() - 1;
+                path[current].delta = true;
+                pdf_fwd = 0.0;
+                pdf_rev = 0.0;
+            }
+            let current = path.len() - 1;
+            path[prev].pdf_rev = path[current].convert_density(pdf_rev, &path[prev]);
+            walk = sample.walk;
+            ray = Ray::new(rec.p, sample.direction);
+
+            // Russian roulette, as in the path tracer.
+            if current >= scene.camera.stats().min_depth() {
+                let survival = beta.max_component().min(1.0);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                beta = beta / survival;
+            }
+        }
+        Point3D::new(0.0, 0.0, 0.0)
+    }
+
+    // Light carried by the path made of the first s vertices of the light subpath and the first
+    // t of the camera subpath, weighted for MIS, along with the pixel to splat it onto when it
+    // does not reach the camera through the current one.
+    fn connect(&self, scene: &Scene, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize) -> Option<(Point3D, Option<(f64, f64)>)> {
+        let connection = self.connect_unweighted(scene, light_path, camera_path, s, t)?;
+        let weight = self.mis_weight(scene, light_path, camera_path, connection.sampled.as_ref(), s, t);
+        Some((connection.contribution * weight, connection.raster))
+    }
+
+    // The same before MIS.
+    fn connect_unweighted(&self, scene: &Scene, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize) -> Option<Connection> {
+        let camera = scene.camera;
+        let pt = &camera_path[t - 1];
+        let mut sampled: Option<Vertex> = None; // Stands in for the last light or camera vertex
+        let mut raster = None;
+
+        let contribution = if s == 0 {
+            // The camera subpath found a light by itself.
+            if !pt.is_emissive() {
+                return None;
+            }
+            pt.beta * pt.emitted(&camera_path[t - 2].p)
+        } else if t == 1 {
+            // Light tracing: connect the light subpath to a new point on the lens.
+            let qs = &light_path[s - 1];
+            if qs.delta {
+                return None;
+            }
+            let lens = camera.sample_lens();
+            raster = Some(camera.raster(&lens, &qs.p)?);
+            let camera_vertex = Vertex::camera(lens, camera.forward(), Point3D::new(1.0, 1.0, 1.0) * camera.lens_area());
+            let to_lens = lens - qs.p;
+            let importance = camera.importance(&lens, &-to_lens);
+            let cos_lens = camera.forward().dot(&to_lens.unit_vector()).abs();
+            let g = qs.normal.dot(&to_lens.unit_vector()).abs() * cos_lens / to_lens.length_squared();
+            let contribution = qs.beta * qs.f(Some(&light_path[s - 2]), &camera_vertex) * camera_vertex.beta * (importance * g);
+            sampled = Some(camera_vertex);
+            contribution
+        } else if s == 1 {
+            // Next-event estimation: connect the camera subpath to a new point on a light.
+            if pt.delta {
+                return None;
+            }
+            let (rec, pdf_position) = scene.lights.sample_surface()?;
+            let light = Vertex::light(rec, pdf_position);
+            let contribution = pt.beta * pt.f(Some(&camera_path[t - 2]), &light) * light.beta * light.f(None, pt) * self.geometry(pt, &light);
+            sampled = Some(light);
+            contribution
+        } else {
+            let qs = &light_path[s - 1];
+            if qs.delta || pt.delta {
+                return None;
+            }
+            // Both subpaths may have dropped the secondary wavelengths, but the whole path
+            // needs weighing for it only once.
+            let hero_only = if qs.hero_only && pt.hero_only { 1.0 / 3.0 } else { 1.0 };
+            qs.beta * qs.f(Some(&light_path[s - 2]), pt) * pt.f(Some(&camera_path[t - 2]), qs) * pt.beta * (self.geometry(qs, pt) * hero_only)
+        };
+
+        if contribution.max_component() <= 0.0 {
+            return None;
+        }
+        if s > 0 {
+            let qs = if s == 1 { sampled.as_ref()? } else { &light_path[s - 1] };
+            let pt = if t == 1 { sampled.as_ref()? } else { pt };
+            if !scene.visible(&qs.p, &pt.p) {
+                return None;
+            }
+        }
+        Some(Connection { contribution, sampled, raster })
+    }
+
+    // Geometry term between two surface points, without visibility.
+    fn geometry(&self, a: &Vertex, b: &Vertex) -> f64 {
+        let ab = b.p - a.p;
+        let direction = ab.unit_vector();
+        a.normal.dot(&direction).abs() * b.normal.dot(&direction).abs() / ab.length_squared()
+    }
+
+    // Power heuristic weight of strategy (s, t) among all strategies that could have built the
+    // same path, from the ratios of their densities to that of (s, t), walking outwards from
+    // the connection along both subpaths.
+    fn mis_weight(&self, scene: &Scene, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+        let camera = scene.camera;
+        if s + t == 2 {
+            return 1.0;
+        }
+        // Delta densities are stored as zero, but only their ratios matter, and those cancel.
+        let remap0 = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(&light_path[s - 1]),
+        };
+        let pt = if t == 1 { sampled.unwrap() } else { &camera_path[t - 1] };
+        let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+        let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+
+        // Reverse densities around the connection, which only this strategy defines.
+        let pt_rev = match qs {
+            Some(qs) => qs.pdf(camera, qs_minus, pt),
+            None => scene.lights.pdf_surface(&pt_minus.unwrap().p, &pt.p),
+        };
+        let pt_minus_rev = pt_minus.map_or(0.0, |pt_minus| match qs {
+            Some(qs) => pt.pdf(camera, Some(qs), pt_minus),
+            None => pt.pdf_light(pt_minus),
+        });
+        let qs_rev = qs.map_or(0.0, |qs| pt.pdf(camera, pt_minus, qs));
+        let qs_minus_rev = qs_minus.map_or(0.0, |qs_minus| qs.unwrap().pdf(camera, Some(pt), qs_minus));
+
+        let mut sum_ri = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            // Neither this strategy nor any with fewer camera vertices walks through the medium.
+            if camera_path[i].in_medium() {
+                break;
+            }
+            let (pdf_rev, pdf_fwd, delta) = if i == t - 1 {
+                (pt_rev, pt.pdf_fwd, false)
+            } else if i == t - 2 {
+                (pt_minus_rev, camera_path[i].pdf_fwd, camera_path[i].delta)
+            } else {
+                (camera_path[i].pdf_rev, camera_path[i].pdf_fwd, camera_path[i].delta)
+            };
+            ri *= remap0(pdf_rev) / remap0(pdf_fwd);
+            if !delta && !camera_path[i - 1].delta {
+                sum_ri += ri * ri;
+            }
+        }
+
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            let vertex = if i == s - 1 { qs.unwrap() } else { &light_path[i] };
+            let (pdf_rev, delta) = if i == s - 1 {
+                (qs_rev, false)
+            } else if i + 2 == s {
+                (qs_minus_rev, vertex.delta)
+            } else {
+                (vertex.pdf_rev, vertex.delta)
+            };
+            ri *= remap0(pdf_rev) / remap0(vertex.pdf_fwd);
+            // Lights here are area lights, so the first vertex can always be connected to.
+            let prev_delta = i > 0 && light_path[i - 1].delta;
+            if !delta && !prev_delta {
+                sum_ri += ri * ri;
+            }
+        }
+        1.0 / (1.0 + sum_ri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plane::*;
+    use crate::sphere::*;
+    use crate::texture::*;
+
+    // Only light tracing, the strategies with a single camera vertex, without MIS.
+    struct LightTracing;
+
+    impl SampleIntegrator for LightTracing {
+        fn li(&self, r: &Ray, camera: &Camera, world: &dyn Hittable, lights: &LightList, film: &Film) -> Point3D {
+            let scene = Scene { camera, world, lights };
+            let camera_path = vec![Vertex::camera(r.origin(), camera.forward(), Point3D::new(1.0, 1.0, 1.0))];
+            let light_path = BdptIntegrator.light_subpath(&scene, camera.stats().max_depth());
+            for s in 2..=light_path.len() {
+                if let Some(Connection { contribution, raster: Some((x, y)), .. }) = BdptIntegrator.connect_unweighted(&scene, &light_path, &camera_path, s, 1) {
+                    film.add_splat(x, y, contribution);
+                }
+            }
+            Point3D::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    // A diffuse ball on the ground, lit by a lamp above it and out of view, and seen out of
+    // focus through a wide lens.
+    fn defocused_scene() -> (HittableList, Camera) {
+        let gray = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))));
+        let red = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.7, 0.2, 0.2))));
+        let lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(4.0, 4.0, 4.0)));
+        let world = HittableList::new(vec![
+            Box::new(Plane::ground(-0.5, gray)),
+            Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.0), 0.5, red)),
+            Box::new(Sphere::new(Point3D::new(0.0, 2.0, -1.0), 0.5, lamp)),
+        ]);
+
+        let stats = CameraStats::new(4.0 / 3.0, 32.0, 256.0, 5, 3);
+        let (lookfrom, lookat) = (Point3D::new(0.0, 0.2, 1.0), Point3D::new(0.0, 0.0, -1.0));
+        let view = CameraView::new(stats, 40.0, lookfrom, lookat, Point3D::new(0.0, 1.0, 0.0), 3.0);
+        let mut camera = Camera::new(stats, view, CameraFocus::new(view, 10.0));
+        camera.set_background(Background::Solid(Point3D::new(0.0, 0.0, 0.0)));
+        (world, camera)
+    }
+
+    // Sum of the image over its top and bottom halves.
+    fn halves(image: &[Point3D]) -> [Point3D; 2] {
+        let (top, bottom) = image.split_at(image.len() / 2);
+        let sum = |pixels: &[Point3D]| pixels.iter().fold(Point3D::new(0.0, 0.0, 0.0), |sum, pixel| sum + *pixel);
+        [sum(top), sum(bottom)]
+    }
+
+    #[test]
+    fn light_tracing_matches_path_tracing_through_a_defocused_lens() {
+        let (world, camera) = defocused_scene();
+        let traced = halves(&LightTracing.render(&camera, &world));
+        let expected = halves(&PathIntegrator.render(&camera, &world));
+        for (traced, expected) in traced.iter().zip(expected.iter()) {
+            assert!((*traced - *expected).length() < 0.1 * expected.length(), "{:?} against {:?}", traced, expected);
+        }
+    }
+}
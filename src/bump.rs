@@ -0,0 +1,150 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::onb::*;
+use std::sync::Arc;
+
+// Detail too fine to model, like the grain of leather, the joints between tiles or ripples on
+// water, painted onto another material by tilting the normal it shades with. A normal map holds
+// the tilted normals themselves, in the frame of the surface's tangents: x along u, y along v and
+// z out of the surface, each mapped from [-1, 1] to a channel in [0, 1]. A bump map holds heights
+// in its first channel, and the normal follows from how they change along u and v.
+//
+// Where the shading normal and the true normal disagree, a direction can lie above one and below
+// the other, which lets light leak through the surface or black out parts of it. The shading
+// normal is bent back towards the true one as far as needed for the mirror reflection of the
+// outgoing direction to stay above the surface, and directions on opposite sides of the two
+// normals are dropped. The BSDF is scaled from the true cosine the integrators weigh it by to the
+// shading one, for light arriving at the camera; light traced from the lights would need the
+// adjoint of that, which only differs noticeably for steep bumps seen at grazing angles.
+#[derive(Debug, Clone)]
+pub struct Bumped {
+    pub base: Arc<Material>,
+    pub detail: SurfaceDetail,
+}
+
+#[derive(Debug, Clone)]
+pub enum SurfaceDetail {
+    NormalMap(Arc<ImageMap>),
+    BumpMap { heights: Arc<ImageMap>, scale: f64 }, // Scale is the height of the full range of the map
+}
+
+// Number of halvings of the angle between the shading and the true normal when bending.
+const BEND_STEPS: usize = 12;
+
+impl Bumped {
+    pub fn normal_map(base: Material, normals: ImageMap) -> Bumped {
+        Bumped { base: Arc::new(base), detail: SurfaceDetail::NormalMap(Arc::new(normals)) }
+    }
+
+    pub fn bump_map(base: Material, heights: ImageMap, scale: f64) -> Bumped {
+        Bumped { base: Arc::new(base), detail: SurfaceDetail::BumpMap { heights: Arc::new(heights), scale } }
+    }
+
+    // Tangents of the surface along u and v, or any two perpendicular to the normal if the
+    // shape does not provide them.
+    fn tangents(rec: &HitRecord, outward: &Point3D) -> (Point3D, Point3D) {
+        if rec.dpdu.cross(&rec.dpdv).length_squared() > 0.0 {
+            return (rec.dpdu, rec.dpdv);
+        }
+        let frame = Onb::new(outward);
+        (frame.u(), frame.v())
+    }
+
+    // Normal of the detail at the hit, on the outside of the surface.
+    fn detail_normal(&self, rec: &HitRecord, outward: &Point3D) -> Point3D {
+        let (dpdu, dpdv) = Bumped::tangents(rec, outward);
+        let normal = match &self.detail {
+            SurfaceDetail::NormalMap(normals) => {
+                let local = normals.value(rec.u, rec.v) * 2.0 - Point3D::new(1.0, 1.0, 1.0);
+                let frame = Onb::with_tangent(outward, &dpdu);
+                // The map's y runs along v, whichever way round the tangents turn.
+                let bitangent = if frame.v().dot(&dpdv) < 0.0 { -frame.v() } else { frame.v() };
+                frame.u() * local.x() + bitangent * local.y() + frame.w() * local.z()
+            }
+            SurfaceDetail::BumpMap { heights, scale } => {
+                // Displace the surface along its normal and take the normal of the result, by
+                // forward differences one pixel of the map wide.
+                let height = |u: f64, v: f64| heights.value(u, v).x() * scale;
+                let (du, dv) = (1.0 / heights.width() as f64, 1.0 / heights.height() as f64);
+                let h = height(rec.u, rec.v);
+                let dhdu = (height(rec.u + du, rec.v) - h) / du;
+                let dhdv = (height(rec.u, rec.v + dv) - h) / dv;
+                (dpdu + *outward * dhdu).cross(&(dpdv + *outward * dhdv))
+            }
+        };
+        if normal.length_squared() == 0.0 || !normal.length_squared().is_finite() {
+            return *outward;
+        }
+        let normal = normal.unit_vector();
+        if normal.dot(outward) < 0.0 { -normal } else { normal }
+    }
+
+    // The normal to shade with for light leaving along wo, on the same side as rec.normal.
+    fn shading_normal(&self, rec: &HitRecord, wo: &Point3D) -> Point3D {
+        let outward = if rec.front_face { rec.normal } else { -rec.normal };
+        let detail = self.detail_normal(rec, &outward);
+        let detail = if rec.front_face { detail } else { -detail };
+
+        // The true normal reflects wo to itself, which always passes, so somewhere between the
+        // two is a normal whose reflection just stays above the surface.
+        let threshold = (0.9 * wo.dot(&rec.normal)).min(0.01);
+        let reflects_above = |normal: &Point3D| (*normal * (2.0 * wo.dot(normal)) - *wo).dot(&rec.normal) >= threshold;
+        if reflects_above(&detail) {
+            return detail;
+        }
+        let blend = |t: f64| (detail * (1.0 - t) + rec.normal * t).unit_vector();
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..BEND_STEPS {
+            let middle = (low + high) / 2.0;
+            if reflects_above(&blend(middle)) {
+                high = middle;
+            } else {
+                low = middle;
+            }
+        }
+        blend(high)
+    }
+
+    // Whether w lies on the same side of the true and the shading normal.
+    fn same_side(rec: &HitRecord, normal: &Point3D, w: &Point3D) -> bool {
+        w.dot(&rec.normal) * w.dot(normal) > 0.0
+    }
+
+    // Turns the cosine with the true normal the integrators multiply by into the shading one.
+    fn cosine_correction(rec: &HitRecord, normal: &Point3D, wi: &Point3D) -> f64 {
+        wi.dot(normal).abs() / wi.dot(&rec.normal).abs()
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        let wo = -r_in.direction().unit_vector();
+        let normal = self.shading_normal(rec, &wo);
+        let mut sample = Scatterable::scatter(self.base.as_ref(), r_in, &HitRecord { normal, ..rec.clone() })?;
+        let wi = sample.direction.unit_vector();
+        if !Bumped::same_side(rec, &normal, &wi) {
+            return None;
+        }
+        if !sample.specular {
+            sample.f = sample.f * Bumped::cosine_correction(rec, &normal, &wi);
+        }
+        Some(sample)
+    }
+
+    pub fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        let normal = self.shading_normal(rec, wo);
+        if !Bumped::same_side(rec, &normal, wi) || !Bumped::same_side(rec, &normal, wo) {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        Scatterable::eval(self.base.as_ref(), &HitRecord { normal, ..rec.clone() }, wi, wo) * Bumped::cosine_correction(rec, &normal, wi)
+    }
+
+    pub fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        let normal = self.shading_normal(rec, wo);
+        if !Bumped::same_side(rec, &normal, wi) || !Bumped::same_side(rec, &normal, wo) {
+            return 0.0;
+        }
+        Scatterable::pdf(self.base.as_ref(), &HitRecord { normal, ..rec.clone() }, wi, wo)
+    }
+}
@@ -2,25 +2,43 @@ use crate::hittable::*;
 use crate::color::*;
 use crate::ray::*;
 use crate::point3d::*;
-use std::fs::{self, File};
-use std::io::{self, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
 use crate::random::*;
-use crate::material::*;
+use crate::integrator::*;
+use crate::aov::*;
+use crate::denoise::*;
+use crate::tonemap::*;
+use crate::spectrum::*;
 use rayon::prelude::*;
 
 pub struct Camera {
     stats: CameraStats,
     view: CameraView,
     focus: CameraFocus,
+    background: Background, // Light arriving from rays that escape the scene
+    integrator: Box<dyn Integrator>, // Light transport algorithm computing each camera ray
+    aovs: Vec<Aov>, // Extra layers written next to the rendered image
+    denoiser: Option<Denoiser>, // Filter run over the image before it is written
+    exposure: f64, // In stops (EV): each one doubles the light before tone mapping
+    tone_map: ToneMap, // Curve bringing the light into the range of the output image
+    spectral: bool, // Trace light by wavelength instead of in RGB, where the integrator can
+}
+
+#[derive(Clone, Copy)]
+pub enum Background {
+    Sky, // White to blue gradient from the horizon up
+    Solid(Point3D),
 }
 
 #[derive(Clone, Copy)]
 pub struct CameraStats {
-    aspect_ratio: f64, // Ratio of image width over height
     image_height: f64, // Rendered image height in pixel count
     image_width: f64, // Rendered image width in pixel count
     pixel_samples_scale: f64, // Count of random samples for each pixel
     max_depth: usize, // Maximum number of ray bounces into scene
+    min_depth: usize, // Number of bounces before paths may be ended by Russian roulette
 }
 
 #[derive(Clone, Copy)]
@@ -29,7 +47,6 @@ pub struct CameraView {
     pixel00_loc: Point3D, // Location of pixel 0, 0
     pixel_delta_u: Point3D, // Offset to pixel to the right
     pixel_delta_v: Point3D, // Offset to pixel below
-    vfov: f64, // Vertical view angle (field of view)
     lookfrom: Point3D, // Point camera is looking from
     lookat: Point3D, // Point camera is looking at
     vup: Point3D, // Camera-relative "up" direction
@@ -44,7 +61,7 @@ pub struct CameraFocus {
 }
 
 impl CameraStats {
-    pub fn new(aspect_ratio: f64, image_width: f64, samples_per_pixel: f64, max_depth: usize) -> CameraStats {
+    pub fn new(aspect_ratio: f64, image_width: f64, samples_per_pixel: f64, max_depth: usize, min_depth: usize) -> CameraStats {
         let image_height = if image_width / aspect_ratio < 1.0 {
             1.0 
         } else {
@@ -52,13 +69,17 @@ impl CameraStats {
         };
         let pixel_samples_scale = 1.0 / samples_per_pixel;
 
-        CameraStats { aspect_ratio, image_height, image_width, pixel_samples_scale, max_depth } 
+        CameraStats { image_height, image_width, pixel_samples_scale, max_depth, min_depth } 
     }
 
     pub fn max_depth(&self) -> usize {
         self.max_depth
     }
 
+    pub fn min_depth(&self) -> usize {
+        self.min_depth
+    }
+
     pub fn height(&self) -> f64 {
         self.image_height
     }
@@ -100,7 +121,7 @@ impl CameraView {
         let pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
 
         // Calculate the location of the upper left pixel.
-        CameraView { center, pixel00_loc, pixel_delta_u, pixel_delta_v, vfov, lookfrom, lookat, vup, focus_dist }
+        CameraView { center, pixel00_loc, pixel_delta_u, pixel_delta_v, lookfrom, lookat, vup, focus_dist }
     }
 }
 
@@ -122,62 +143,96 @@ impl CameraFocus {
 
 impl Camera {
     pub fn new(stats: CameraStats, view: CameraView, focus: CameraFocus) -> Camera {
-        Camera { stats, view, focus }
+        Camera { stats, view, focus, background: Background::Sky, integrator: Box::new(PathIntegrator), aovs: Vec::new(), denoiser: None, exposure: 0.0, tone_map: ToneMap::Clamp, spectral: false }
     }
 
     pub fn stats(&self) -> CameraStats {
         self.stats
     }
 
-    pub fn view(&self) -> CameraView {
-        self.view
+    pub fn set_integrator(&mut self, integrator: Box<dyn Integrator>) {
+        self.integrator = integrator;
+    }
+
+    pub fn set_aovs(&mut self, aovs: Vec<Aov>) {
+        self.aovs = aovs;
+    }
+
+    pub fn set_denoiser(&mut self, denoiser: Option<Denoiser>) {
+        self.denoiser = denoiser;
+    }
+
+    pub fn set_exposure(&mut self, exposure: f64) {
+        self.exposure = exposure;
+    }
+
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.tone_map = tone_map;
+    }
+
+    // In spectral mode, integrators that support it trace each path at its own wavelengths,
+    // showing dispersion. The photon mapping integrators always work in RGB.
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
+    pub fn spectral(&self) -> bool {
+        self.spectral
     }
 
-    pub fn focus(&self) -> CameraFocus {
-        self.focus
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
     }
 
     pub fn render(&self, world: &dyn Hittable, file_name: &str) -> io::Result<()> {
         let mut pic = format!("P3\n{} {}\n255\n", self.stats.image_width, self.stats.image_height);
 
-        let samples_per_pixel = (1.0 / self.stats.pixel_samples_scale) as usize;
+        let (image, variance) = self.integrator.render_with_variance(self, world);
+        let image = match &self.denoiser {
+            Some(denoiser) => denoiser.denoise(self, world, &image, variance.as_deref()),
+            None => image,
+        };
+        let width = self.stats.width() as usize;
+        let scale = 2.0_f64.powf(self.exposure);
         let pixels = (0..self.stats.height() as usize).into_par_iter().map(|h| {
-            (0..self.stats.width() as usize).into_par_iter().map(|w| {
-                let mut pixel_color = Point3D::new(0.0, 0.0, 0.0);
-                for _ in 0..samples_per_pixel {
-                    let r = self.get_ray(w as f64, h as f64);
-                    pixel_color = pixel_color + self.ray_color(&r, self.stats.max_depth, world);
-                }
-                pixel_color = pixel_color / samples_per_pixel as f64;
-                pixel_color = Point3D::new(pixel_color.x().sqrt(), pixel_color.y().sqrt(), pixel_color.z().sqrt());
-                let min: f64 = 0.000;
-                let max: f64 = 0.999;
-                let rbyte: usize = (256.0 * clamp(pixel_color.x(), min, max)) as usize;
-                let gbyte: usize = (256.0 * clamp(pixel_color.y(), min, max)) as usize;
-                let bbyte: usize = (256.0 * clamp(pixel_color.z(), min, max)) as usize;
+            (0..width).into_par_iter().map(|w| {
+                let pixel_color = self.tone_map.apply(image[h * width + w] * scale);
+                let pixel_color = Point3D::new(linear_to_srgb(pixel_color.x()), linear_to_srgb(pixel_color.y()), linear_to_srgb(pixel_color.z()));
+                let rbyte: usize = (255.0 * clamp(pixel_color.x(), 0.0, 1.0)).round() as usize;
+                let gbyte: usize = (255.0 * clamp(pixel_color.y(), 0.0, 1.0)).round() as usize;
+                let bbyte: usize = (255.0 * clamp(pixel_color.z(), 0.0, 1.0)).round() as usize;
                 format!("{} {} {}\n", rbyte, gbyte, bbyte)
             }).collect::<Vec<String>>().join("")
         }).collect::<Vec<String>>().join("");
     
         pic = format!("{}{}", &pic, pixels);
-        fs::write(file_name.to_string(), pic)?;
+        fs::write(file_name, pic)?;
+
+        // Each AOV goes to its own file, named after the image: image.depth.pfm and so on.
+        let layers = render_aovs(self, world, &self.aovs);
+        for (aov, layer) in self.aovs.iter().zip(layers.iter()) {
+            let path = Path::new(file_name).with_extension(format!("{}.pfm", aov.name()));
+            write_pfm(&path, width, self.stats.height() as usize, aov.channels(), layer)?;
+        }
         Ok(())
     }
 
-    pub fn ray_color(&self, r: &Ray, max_depth: usize, world: &dyn Hittable) -> Point3D { 
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if max_depth == 0 {
-            Point3D::new(0.0, 0.0, 0.0)
-        } else if let Some(rec) = world.hit(r, 0.001, f64::MAX) { 
-            match Scatterable::scatter(&rec.material, r, &rec) {
-                (Some(scattered), Some(accentuation)) => accentuation * self.ray_color(&scattered, max_depth - 1, world),
-                _ => Point3D::new(0.0, 0.0, 0.0),
+    pub fn background_color(&self, r: &Ray) -> Point3D {
+        let color = match self.background {
+            Background::Sky => {
+                let unit_direction = r.direction().unit_vector();
+                let a: f64 = 0.5 * (unit_direction.y() + 1.0);
+                Point3D::new(1.0, 1.0, 1.0) * (1.0 - a) + Point3D::new(0.5, 0.7, 1.0) * a 
             }
-        } else { 
-            let unit_direction = r.direction().unit_vector();
-            let a: f64 = 0.5 * (unit_direction.y() + 1.0);
-            Point3D::new(1.0, 1.0, 1.0) * (1.0 - a) + Point3D::new(0.5, 0.7, 1.0) * a 
-        } 
+            Background::Solid(color) => color,
+        };
+        from_rgb(color)
+    }
+
+    // The ray from the center of the lens through the center of pixel i, j.
+    pub fn get_center_ray(&self, i: f64, j: f64) -> Ray {
+        let pixel_center = self.view.pixel00_loc + (self.view.pixel_delta_u * i) + (self.view.pixel_delta_v * j);
+        Ray::new(self.view.center, pixel_center - self.view.center)
     }
 
     pub fn get_ray(&self, i: f64, j: f64) -> Ray {
@@ -192,6 +247,67 @@ impl Camera {
         Ray::new(ray_origin, ray_direction)
     }
 
+    // Unit vector along which the camera looks.
+    pub fn forward(&self) -> Point3D {
+        (self.view.lookat - self.view.lookfrom).unit_vector()
+    }
+
+    // Area of the lens camera rays start from. A pinhole camera counts as a lens of area one so
+    // that the densities below need no special case.
+    pub fn lens_area(&self) -> f64 {
+        if self.focus.defocus_angle <= 0.0 {
+            1.0
+        } else {
+            std::f64::consts::PI * self.focus.defocus_disk_u.length_squared()
+        }
+    }
+
+    // A random point on the lens, picked uniformly over its area.
+    pub fn sample_lens(&self) -> Point3D {
+        if self.focus.defocus_angle <= 0.0 { self.view.center } else { self.defocus_disk_sample() }
+    }
+
+    // Where the ray from lens through p lands on the image, in pixel units from its top left
+    // corner, or None when p is outside the view.
+    pub fn raster(&self, lens: &Point3D, p: &Point3D) -> Option<(f64, f64)> {
+        let direction = *p - *lens;
+        let along = direction.dot(&self.forward());
+        if along <= 0.0 {
+            return None;
+        }
+        // Every ray through a point of the focus plane comes from that point's pixel.
+        let on_focus_plane = *lens + direction * (self.view.focus_dist / along);
+        let upper_left = self.view.pixel00_loc - (self.view.pixel_delta_u + self.view.pixel_delta_v) * 0.5;
+        let offset = on_focus_plane - upper_left;
+        let x = offset.dot(&self.view.pixel_delta_u) / self.view.pixel_delta_u.length_squared();
+        let y = offset.dot(&self.view.pixel_delta_v) / self.view.pixel_delta_v.length_squared();
+        if x < 0.0 || y < 0.0 || x >= self.stats.image_width || y >= self.stats.image_height {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    // Solid angle density of get_ray picking direction for a ray starting at lens.
+    pub fn pdf_direction(&self, lens: &Point3D, direction: &Point3D) -> f64 {
+        if self.raster(lens, &(*lens + *direction)).is_none() {
+            return 0.0;
+        }
+        let cos_theta = direction.unit_vector().dot(&self.forward());
+        let image_area = self.view.pixel_delta_u.length() * self.stats.image_width
+            * self.view.pixel_delta_v.length() * self.stats.image_height;
+        self.view.focus_dist * self.view.focus_dist / (image_area * cos_theta.powi(3))
+    }
+
+    // Sensitivity of the whole image to light arriving at lens from direction, normalized so that
+    // it integrates to one over the lens area and the directions into the image.
+    pub fn importance(&self, lens: &Point3D, direction: &Point3D) -> f64 {
+        let cos_theta = direction.unit_vector().dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        self.pdf_direction(lens, direction) / (self.lens_area() * cos_theta)
+    }
+
     fn defocus_disk_sample(&self) -> Point3D {
         let p = Point3D::random_in_unit_disk();
         self.view.center + (self.focus.defocus_disk_u * p.x()) + (self.focus.defocus_disk_v * p.y())
@@ -0,0 +1,112 @@
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference, // Left solid with the right solid carved out of it
+}
+
+pub struct Csg {
+    op: CsgOp,
+    left: Box<dyn Solid>,
+    right: Box<dyn Solid>,
+}
+
+impl CsgOp {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg { op, left, right }
+    }
+
+    pub fn union(left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg::new(CsgOp::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        // Skip finding the spans of both operands when the ray misses the result's box.
+        if !self.bounding_box().hit(r, ray_tmin, ray_tmax) {
+            return None;
+        }
+        Span::first_hit(&self.spans(r), r, ray_tmin, ray_tmax)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.op {
+            CsgOp::Union => left.surrounding(&right),
+            CsgOp::Intersection => left.overlap(&right),
+            CsgOp::Difference => left,
+        }
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        self.left.collect_materials(materials);
+        self.right.collect_materials(materials);
+    }
+}
+
+impl Solid for Csg {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        // Every span boundary of either operand is an event where the ray may cross the surface
+        // of the combined solid. Sweep them in order and emit a span whenever the combined
+        // inside/outside state flips.
+        let mut events = Vec::new();
+        for (from_right, solid) in [(false, &self.left), (true, &self.right)].iter() {
+            for span in solid.spans(r).iter() {
+                events.push((*from_right, true, span.enter.clone()));
+                events.push((*from_right, false, span.exit.clone()));
+            }
+        }
+        events.sort_by(|a, b| a.2.t.partial_cmp(&b.2.t).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut spans = Vec::new();
+        let mut depth_left = 0;
+        let mut depth_right = 0;
+        let mut enter: Option<HitRecord> = None;
+        for (from_right, entering, boundary) in events.iter() {
+            let step = if *entering { 1 } else { -1 };
+            if *from_right { depth_right += step } else { depth_left += step }
+
+            let mut rec = boundary.clone();
+            if *from_right && self.op == CsgOp::Difference {
+                // The carved-out surface faces into the removed solid.
+                rec.normal = -rec.normal;
+            }
+
+            let inside = self.op.inside(depth_left > 0, depth_right > 0);
+            match enter {
+                None if inside => enter = Some(rec),
+                Some(start) if !inside => {
+                    spans.push(Span::new(start, rec));
+                    enter = None;
+                }
+                _ => {}
+            }
+        }
+        spans
+    }
+}
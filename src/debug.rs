@@ -0,0 +1,134 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::integrator::*;
+use crate::color::clamp;
+use crate::onb::*;
+use crate::random::*;
+use crate::film::*;
+use crate::subsurface::*;
+
+// Integrators that show one property of the first surface each camera ray hits, for debugging
+// scenes rather than rendering them.
+
+pub struct NormalsIntegrator;
+
+pub struct DepthIntegrator {
+    max_distance: f64, // Distance shown as black; nearer surfaces are brighter
+}
+
+pub struct AlbedoIntegrator;
+
+pub struct AmbientOcclusionIntegrator {
+    samples: usize, // Occlusion rays per camera ray
+    max_distance: f64, // Occluders further away than this are ignored
+}
+
+// Colors each camera ray by the number of bounces the path tracer follows it for, from blue
+// (none) to red (the camera's max depth).
+pub struct BounceHeatmapIntegrator;
+
+impl SampleIntegrator for NormalsIntegrator {
+    fn li(&self, r: &Ray, _camera: &Camera, world: &dyn Hittable, _lights: &LightList, _film: &Film) -> Point3D {
+        match world.hit(r, 0.001, f64::MAX) {
+            Some(rec) => {
+                let outward = if rec.front_face { rec.normal } else { -rec.normal };
+                (outward + Point3D::new(1.0, 1.0, 1.0)) * 0.5
+            }
+            None => Point3D::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl DepthIntegrator {
+    pub fn new(max_distance: f64) -> DepthIntegrator {
+        DepthIntegrator { max_distance }
+    }
+}
+
+impl SampleIntegrator for DepthIntegrator {
+    fn li(&self, r: &Ray, _camera: &Camera, world: &dyn Hittable, _lights: &LightList, _film: &Film) -> Point3D {
+        match world.hit(r, 0.001, f64::MAX) {
+            Some(rec) => {
+                let distance = rec.t * r.direction().length();
+                let brightness = 1.0 - clamp(distance / self.max_distance, 0.0, 1.0);
+                Point3D::new(brightness, brightness, brightness)
+            }
+            None => Point3D::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl SampleIntegrator for AlbedoIntegrator {
+    fn li(&self, r: &Ray, camera: &Camera, world: &dyn Hittable, _lights: &LightList, _film: &Film) -> Point3D {
+        match world.hit(r, 0.001, f64::MAX) {
+            Some(rec) => rec.material.albedo(&rec),
+            None => camera.background_color(r),
+        }
+    }
+}
+
+impl AmbientOcclusionIntegrator {
+    pub fn new(samples: usize, max_distance: f64) -> AmbientOcclusionIntegrator {
+        AmbientOcclusionIntegrator { samples, max_distance }
+    }
+}
+
+impl SampleIntegrator for AmbientOcclusionIntegrator {
+    fn li(&self, r: &Ray, _camera: &Camera, world: &dyn Hittable, _lights: &LightList, _film: &Film) -> Point3D {
+        let rec = match world.hit(r, 0.001, f64::MAX) {
+            Some(rec) => rec,
+            None => return Point3D::new(1.0, 1.0, 1.0),
+        };
+        // Cosine-weighted rays make the unoccluded fraction the cosine-weighted visibility.
+        let uvw = Onb::new(&rec.normal);
+        let unoccluded = (0..self.samples).filter(|_| {
+            let direction = uvw.transform(&Point3D::random_cosine_direction());
+            world.hit(&Ray::new(rec.p, direction), 0.001, self.max_distance).is_none()
+        }).count();
+        let visibility = unoccluded as f64 / self.samples as f64;
+        Point3D::new(visibility, visibility, visibility)
+    }
+}
+
+impl SampleIntegrator for BounceHeatmapIntegrator {
+    fn li(&self, r: &Ray, camera: &Camera, world: &dyn Hittable, _lights: &LightList, _film: &Film) -> Point3D {
+        // Follow the same random walk as the path tracer, without gathering any light.
+        let mut throughput = Point3D::new(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        let mut walk: Option<Walk> = None; // Through a translucent object, when the path is on one
+        let mut bounces = 0;
+        while bounces < camera.stats().max_depth() {
+            let rec = match hit_on_walk(world, &ray, walk.as_ref(), 0.001, f64::MAX) {
+                Some(rec) => rec,
+                None => break,
+            };
+            let sample = match Scatterable::scatter(&rec.material, &ray, &rec) {
+                Some(sample) => sample,
+                None => break,
+            };
+            bounces += 1;
+            throughput = throughput * sample.weight(&rec.normal);
+            walk = sample.walk;
+            ray = Ray::new(rec.p, sample.direction);
+            if bounces >= camera.stats().min_depth() {
+                let survival = throughput.max_component().min(1.0);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+        heatmap(bounces as f64 / camera.stats().max_depth() as f64)
+    }
+}
+
+// Blue through cyan, green and yellow to red as x goes from 0 to 1.
+pub fn heatmap(x: f64) -> Point3D {
+    let x = clamp(x, 0.0, 1.0) * 4.0;
+    let ramp = |from: f64| clamp(x - from, 0.0, 1.0);
+    Point3D::new(ramp(2.0), ramp(0.0) - ramp(3.0), 1.0 - ramp(1.0))
+}
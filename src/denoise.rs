@@ -0,0 +1,183 @@
+use crate::point3d::*;
+use crate::hittable::*;
+use crate::camera::*;
+use crate::color::*;
+use crate::aov::*;
+use rayon::prelude::*;
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), with the variance guidance of
+// SVGF (Schied et al. 2017). Each pass blurs the image with a 5x5 kernel whose taps are spread
+// twice as far apart as in the pass before, and weighs every tap down where the first surfaces
+// differ in normal or depth, or where its color differs from the center by more than the noise
+// explains. Lighting is filtered apart from the albedo so that textures stay sharp.
+pub struct Denoiser {
+    iterations: usize, // Number of passes; the last one reaches 2^(iterations+1) pixels away
+    sigma_luminance: f64, // Luminance difference tolerated, in standard deviations of the noise
+    sigma_normal: f64, // Exponent of the normal similarity; larger keeps creases sharper
+    sigma_depth: f64, // Depth difference tolerated, relative to the local depth gradient
+}
+
+// The first-hit properties the filter is guided by, one per pixel.
+struct Guides {
+    width: usize,
+    height: usize,
+    albedo: Vec<Point3D>,
+    normal: Vec<Point3D>,
+    depth: Vec<f64>,
+    depth_gradient: Vec<f64>, // Change of depth to the next pixel along the surface
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+impl Denoiser {
+    pub fn new(iterations: usize) -> Denoiser {
+        Denoiser { iterations, sigma_luminance: 4.0, sigma_normal: 128.0, sigma_depth: 1.0 }
+    }
+
+    pub fn set_sigma_luminance(&mut self, sigma_luminance: f64) {
+        self.sigma_luminance = sigma_luminance;
+    }
+
+    pub fn set_sigma_normal(&mut self, sigma_normal: f64) {
+        self.sigma_normal = sigma_normal;
+    }
+
+    pub fn set_sigma_depth(&mut self, sigma_depth: f64) {
+        self.sigma_depth = sigma_depth;
+    }
+
+    // Denoises a row-major image rendered by camera. variance is the variance of each pixel's
+    // luminance estimate; without it the noise is guessed from the spread of neighbouring pixels.
+    pub fn denoise(&self, camera: &Camera, world: &dyn Hittable, image: &[Point3D], variance: Option<&[f64]>) -> Vec<Point3D> {
+        let stats = camera.stats();
+        let (width, height) = (stats.width() as usize, stats.height() as usize);
+        let layers = render_aovs(camera, world, &[Aov::Albedo, Aov::Normal, Aov::Depth]);
+        let depth: Vec<f64> = layers[2].iter().map(|depth| depth.x()).collect();
+        let depth_gradient = depth_gradient(&depth, width, height);
+        let guides = Guides { width, height, albedo: layers[0].clone(), normal: layers[1].clone(), depth, depth_gradient };
+
+        // Divide out the albedo, where there is one to divide by.
+        let mut lighting: Vec<Point3D> = image.iter().zip(guides.albedo.iter()).map(|(color, albedo)| {
+            Point3D::new(demodulate(color.x(), albedo.x()), demodulate(color.y(), albedo.y()), demodulate(color.z(), albedo.z()))
+        }).collect();
+        let mut variance = match variance {
+            Some(variance) => lighting.iter().zip(image.iter()).zip(variance.iter()).map(|((lighting, color), variance)| {
+                // Demodulating scales the luminance, and its variance with the square.
+                let scale = if luminance(color) > 0.0 { luminance(lighting) / luminance(color) } else { 1.0 };
+                variance * scale * scale
+            }).collect(),
+            None => estimate_variance(&lighting, width, height),
+        };
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let filtered: Vec<(Point3D, f64)> = (0..width * height).into_par_iter().map(|index| {
+                self.filter_pixel(index, step, &lighting, &variance, &guides)
+            }).collect();
+            lighting = filtered.iter().map(|(color, _)| *color).collect();
+            variance = filtered.iter().map(|(_, variance)| *variance).collect();
+        }
+
+        lighting.iter().zip(guides.albedo.iter()).map(|(lighting, albedo)| {
+            Point3D::new(remodulate(lighting.x(), albedo.x()), remodulate(lighting.y(), albedo.y()), remodulate(lighting.z(), albedo.z()))
+        }).collect()
+    }
+
+    // Filters one pixel in the pass with taps step pixels apart, returning its color and the
+    // variance of that weighted average.
+    fn filter_pixel(&self, index: usize, step: usize, lighting: &[Point3D], variance: &[f64], guides: &Guides) -> (Point3D, f64) {
+        let (width, height) = (guides.width, guides.height);
+        let (x, y) = ((index % width) as i64, (index / width) as i64);
+        let center_luminance = luminance(&lighting[index]);
+        let center_deviation = blurred_variance(variance, x, y, width, height).sqrt();
+        let mut sum = Point3D::new(0.0, 0.0, 0.0);
+        let mut sum_variance = 0.0;
+        let mut sum_weight = 0.0;
+        for (dy, ky) in KERNEL.iter().enumerate() {
+            for (dx, kx) in KERNEL.iter().enumerate() {
+                let offset = ((dx as i64 - 2) * step as i64, (dy as i64 - 2) * step as i64);
+                let (qx, qy) = (x + offset.0, y + offset.1);
+                if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                    continue;
+                }
+                let q = qy as usize * width + qx as usize;
+                let weight = if q == index {
+                    kx * ky
+                } else {
+                    let distance = ((offset.0 * offset.0 + offset.1 * offset.1) as f64).sqrt();
+                    let normal_weight = guides.normal[index].dot(&guides.normal[q]).max(0.0).powf(self.sigma_normal);
+                    let depth_weight = self.depth_weight(guides.depth[index], guides.depth[q], guides.depth_gradient[index] * distance);
+                    let luminance_difference = (center_luminance - luminance(&lighting[q])).abs();
+                    let luminance_weight = (-luminance_difference / (self.sigma_luminance * center_deviation + 1e-10)).exp();
+                    kx * ky * normal_weight * depth_weight * luminance_weight
+                };
+                sum = sum + lighting[q] * weight;
+                sum_variance += weight * weight * variance[q];
+                sum_weight += weight;
+            }
+        }
+        (sum / sum_weight, sum_variance / (sum_weight * sum_weight))
+    }
+
+    // Likeness of two first-hit depths, expected to differ by about expected along a surface.
+    fn depth_weight(&self, depth: f64, other: f64, expected: f64) -> f64 {
+        match (depth.is_finite(), other.is_finite()) {
+            (false, false) => 1.0, // Both see the background
+            (true, true) => (-(depth - other).abs() / (self.sigma_depth * expected + 1e-10)).exp(),
+            _ => 0.0,
+        }
+    }
+}
+
+fn demodulate(color: f64, albedo: f64) -> f64 {
+    if albedo > 1e-3 { color / albedo } else { color }
+}
+
+fn remodulate(lighting: f64, albedo: f64) -> f64 {
+    if albedo > 1e-3 { lighting * albedo } else { lighting }
+}
+
+// Pixels of the 3x3 neighbourhood of x, y that lie inside the image.
+fn neighbours(x: i64, y: i64, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    (-1..=1).flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |(qx, qy)| *qx >= 0 && *qy >= 0 && *qx < width as i64 && *qy < height as i64)
+        .map(move |(qx, qy)| qy as usize * width + qx as usize)
+}
+
+// Variance averaged over the 3x3 neighbourhood, which steadies the luminance weights against the
+// noise in the variance itself.
+fn blurred_variance(variance: &[f64], x: i64, y: i64, width: usize, height: usize) -> f64 {
+    let (sum, count) = neighbours(x, y, width, height).fold((0.0, 0.0), |(sum, count), q| (sum + variance[q], count + 1.0));
+    sum / count
+}
+
+// Spread of the luminance over each pixel's 3x3 neighbourhood, standing in for the variance of
+// integrators that cannot report it.
+fn estimate_variance(image: &[Point3D], width: usize, height: usize) -> Vec<f64> {
+    (0..width * height).into_par_iter().map(|index| {
+        let (x, y) = ((index % width) as i64, (index / width) as i64);
+        let (sum, sum_squares, count) = neighbours(x, y, width, height).fold((0.0, 0.0, 0.0), |(sum, sum_squares, count), q| {
+            let l = luminance(&image[q]);
+            (sum + l, sum_squares + l * l, count + 1.0)
+        });
+        let mean = sum / count;
+        (sum_squares / count - mean * mean).max(0.0)
+    }).collect()
+}
+
+// How fast depth changes per pixel on the surface seen, so that taps on a slanted surface are
+// not taken for a step between surfaces. Along each axis the smaller of the two one-sided
+// differences is taken, which ignores a step to another surface on one side.
+fn depth_gradient(depth: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let difference = |index: usize, other: Option<usize>| match other {
+        Some(other) if depth[index].is_finite() && depth[other].is_finite() => (depth[other] - depth[index]).abs(),
+        _ => f64::INFINITY,
+    };
+    (0..width * height).into_par_iter().map(|index| {
+        let (x, y) = (index % width, index / width);
+        let along_x = difference(index, (x > 0).then(|| index - 1)).min(difference(index, (x + 1 < width).then(|| index + 1)));
+        let along_y = difference(index, (y > 0).then(|| index - width)).min(difference(index, (y + 1 < height).then(|| index + width)));
+        // An axis with no usable neighbour, at the edge of the surface, says nothing.
+        [along_x, along_y].iter().filter(|d| d.is_finite()).fold(0.0, |gradient, d| f64::max(gradient, *d))
+    }).collect()
+}
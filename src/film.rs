@@ -0,0 +1,40 @@
+use crate::point3d::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Light that lands on the image away from the pixel being rendered, such as light traced from the
+// lights towards the camera. Splats from every thread are summed here and added to the image
+// once all pixels are done. Each channel of each pixel is summed on its own, as the bits of an
+// f64 swapped in atomically, so that threads only wait on each other when they splat onto the
+// same pixel at the same time.
+pub struct Film {
+    width: usize,
+    height: usize,
+    splats: Vec<[AtomicU64; 3]>, // Row-major sum of the splats on each pixel
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        let splats = (0..width * height).map(|_| [0.0f64; 3].map(|zero| AtomicU64::new(zero.to_bits()))).collect();
+        Film { width, height, splats }
+    }
+
+    // Adds color to the pixel at x, y, in pixel units from the top left corner of the image.
+    pub fn add_splat(&self, x: f64, y: f64, color: Point3D) {
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return;
+        }
+        let pixel = &self.splats[y as usize * self.width + x as usize];
+        for (channel, value) in pixel.iter().zip([color.x(), color.y(), color.z()]) {
+            if value != 0.0 {
+                let _ = channel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + value).to_bits()));
+            }
+        }
+    }
+
+    pub fn splats(&self) -> Vec<Point3D> {
+        self.splats.iter().map(|pixel| {
+            let [r, g, b] = pixel.each_ref().map(|channel| f64::from_bits(channel.load(Ordering::Relaxed)));
+            Point3D::new(r, g, b)
+        }).collect()
+    }
+}
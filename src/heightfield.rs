@@ -0,0 +1,231 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use crate::texture::*;
+use std::io;
+
+// Terrain over a regular grid of height samples. Each grid cell is split into two triangles, but
+// the triangles are never stored: rays walk a min-max mipmap (a quadtree whose nodes know the lowest
+// and highest points beneath them) and only test the cells whose bounds they actually pass through.
+pub struct Heightfield {
+    heights: Vec<f64>, // Row-major samples, each a fraction of the full height
+    width: usize, // Number of samples along x
+    depth: usize, // Number of samples along z
+    origin: Point3D, // World position of the first sample at height zero
+    size: Point3D, // Extent of the terrain along x and z, and its full height along y
+    mips: Vec<Vec<(f64, f64)>>, // Lowest and highest sample under each cell, then under each 2x2 block of the level below
+    normals: Vec<Point3D>, // Per-sample normals, interpolated across cells for smooth shading
+    material: Material,
+}
+
+impl Heightfield {
+    pub fn new(heights: Vec<f64>, width: usize, depth: usize, origin: Point3D, size: Point3D, material: Material) -> Heightfield {
+        assert!(width >= 2 && depth >= 2 && heights.len() == width * depth, "heightfield needs at least 2x2 samples");
+        let mut heightfield = Heightfield { heights, width, depth, origin, size, mips: Vec::new(), normals: Vec::new(), material };
+        heightfield.build_mips();
+        heightfield.build_normals();
+        heightfield
+    }
+
+    // Reads heights from the first channel of a grayscale or color PNG, scaling the full range of
+    // its bit depth (8 or 16 bits) to the height of the terrain.
+    pub fn from_png(path: &str, origin: Point3D, size: Point3D, material: Material) -> io::Result<Heightfield> {
+        let image = ImageMap::from_png(path)?;
+        let (width, depth) = (image.width(), image.height());
+        let heights = (0..width * depth).map(|k| image.texel(k % width, k / width).x()).collect();
+        Ok(Heightfield::new(heights, width, depth, origin, size, material))
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[j * self.width + i] * self.size.y()
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3D {
+        self.origin + Point3D::new(
+            i as f64 / (self.width - 1) as f64 * self.size.x(),
+            self.height(i, j),
+            j as f64 / (self.depth - 1) as f64 * self.size.z(),
+        )
+    }
+
+    fn build_mips(&mut self) {
+        let (mut cols, mut rows) = (self.width - 1, self.depth - 1);
+        let mut level: Vec<(f64, f64)> = (0..rows).flat_map(|j| (0..cols).map(move |i| (i, j))).map(|(i, j)| {
+            let corners = [self.height(i, j), self.height(i + 1, j), self.height(i, j + 1), self.height(i + 1, j + 1)];
+            corners.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), h| (low.min(*h), high.max(*h)))
+        }).collect();
+        loop {
+            self.mips.push(level.clone());
+            if cols == 1 && rows == 1 {
+                break;
+            }
+            let (next_cols, next_rows) = (cols.div_ceil(2), rows.div_ceil(2));
+            let mut next = vec![(f64::INFINITY, f64::NEG_INFINITY); next_cols * next_rows];
+            for j in 0..rows {
+                for i in 0..cols {
+                    let parent = (j / 2) * next_cols + i / 2;
+                    let (low, high) = level[j * cols + i];
+                    next[parent] = (next[parent].0.min(low), next[parent].1.max(high));
+                }
+            }
+            level = next;
+            cols = next_cols;
+            rows = next_rows;
+        }
+    }
+
+    fn build_normals(&mut self) {
+        // Central differences of the height in world units, one-sided at the borders.
+        let dx = self.size.x() / (self.width - 1) as f64;
+        let dz = self.size.z() / (self.depth - 1) as f64;
+        self.normals = (0..self.depth).flat_map(|j| (0..self.width).map(move |i| (i, j))).map(|(i, j)| {
+            let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.width - 1));
+            let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.depth - 1));
+            let slope_x = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f64 * dx);
+            let slope_z = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f64 * dz);
+            Point3D::new(-slope_x, 1.0, -slope_z).unit_vector()
+        }).collect();
+    }
+
+    // Bounds of the quadtree node at (i, j) of the given mip level.
+    fn node_box(&self, level: usize, i: usize, j: usize) -> Aabb {
+        let span = 1 << level;
+        let (i0, j0) = (i * span, j * span);
+        let (i1, j1) = ((i0 + span).min(self.width - 1), (j0 + span).min(self.depth - 1));
+        let cols = (self.width - 1).div_ceil(span);
+        let (bottom, top) = self.mips[level][j * cols + i];
+        // Pad vertically so that perfectly flat nodes still have some thickness.
+        let low = Point3D::new(self.vertex(i0, j0).x(), self.origin.y() + bottom - 1e-6, self.vertex(i0, j0).z());
+        let high = Point3D::new(self.vertex(i1, j1).x(), self.origin.y() + top + 1e-6, self.vertex(i1, j1).z());
+        Aabb::new(low, high)
+    }
+
+    // Finds the closest hit below the quadtree node (level, i, j).
+    fn traverse(&self, r: &Ray, node: (usize, usize, usize), ray_tmin: f64, ray_tmax: f64, closest: &mut Option<HitRecord>) {
+        let (level, i, j) = node;
+        let ray_tmax = closest.as_ref().map_or(ray_tmax, |rec| rec.t);
+        if level == 0 {
+            if let Some(rec) = self.hit_cell(r, i, j, ray_tmin, ray_tmax) {
+                *closest = Some(rec);
+            }
+            return;
+        }
+
+        // Visit the children the ray passes through, nearest first, so that a hit in a near
+        // child lets the far ones be skipped.
+        let child_cols = (self.width - 1).div_ceil(1 << (level - 1));
+        let child_rows = (self.depth - 1).div_ceil(1 << (level - 1));
+        let mut children = Vec::with_capacity(4);
+        for (ci, cj) in [(2 * i, 2 * j), (2 * i + 1, 2 * j), (2 * i, 2 * j + 1), (2 * i + 1, 2 * j + 1)].iter() {
+            if *ci < child_cols && *cj < child_rows {
+                if let Some((t_enter, _)) = self.node_box(level - 1, *ci, *cj).hit_interval(r, ray_tmin, ray_tmax) {
+                    children.push((t_enter, *ci, *cj));
+                }
+            }
+        }
+        children.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        for (t_enter, ci, cj) in children.iter() {
+            if closest.as_ref().is_some_and(|rec| rec.t < *t_enter) {
+                break;
+            }
+            self.traverse(r, (level - 1, *ci, *cj), ray_tmin, ray_tmax, closest);
+        }
+    }
+
+    fn hit_cell(&self, r: &Ray, i: usize, j: usize, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest: Option<HitRecord> = None;
+        for triangle in [[corners[0], corners[2], corners[1]], [corners[0], corners[3], corners[2]]].iter() {
+            let tmax = closest.as_ref().map_or(ray_tmax, |rec| rec.t);
+            if let Some(rec) = self.hit_triangle(r, triangle, ray_tmin, tmax) {
+                closest = Some(rec);
+            }
+        }
+        closest
+    }
+
+    fn hit_triangle(&self, r: &Ray, triangle: &[(usize, usize); 3], ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        // Moller-Trumbore: solve for t and the barycentric coordinates of the hit together.
+        let p0 = self.vertex(triangle[0].0, triangle[0].1);
+        let p1 = self.vertex(triangle[1].0, triangle[1].1);
+        let p2 = self.vertex(triangle[2].0, triangle[2].1);
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let pvec = r.direction().cross(&edge2);
+        let det = edge1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin() - p0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&edge1);
+        let b2 = r.direction().dot(&qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&qvec) * inv_det;
+        if t <= ray_tmin || t >= ray_tmax {
+            return None;
+        }
+
+        let normal_at = |(i, j): (usize, usize)| self.normals[j * self.width + i];
+        let smooth_normal = (normal_at(triangle[0]) * (1.0 - b1 - b2) + normal_at(triangle[1]) * b1 + normal_at(triangle[2]) * b2).unit_vector();
+        let geometric_normal = edge1.cross(&edge2).unit_vector();
+        let front_face = r.direction().dot(&geometric_normal) < 0.0;
+
+        let p = r.at(t);
+        let u = (p.x() - self.origin.x()) / self.size.x();
+        let v = (p.z() - self.origin.z()) / self.size.z();
+        let mut rec = HitRecord::new(p, if front_face { smooth_normal } else { -smooth_normal }, t, front_face, self.material.clone(), u, v);
+        if rec.is_cut_out(r) {
+            return None;
+        }
+        // u and v follow x and z, and the height follows the plane of the triangle.
+        let (gx, gy, gz) = (geometric_normal.x(), geometric_normal.y(), geometric_normal.z());
+        let dpdu = Point3D::new(self.size.x(), -self.size.x() * gx / gy, 0.0);
+        let dpdv = Point3D::new(0.0, -self.size.z() * gz / gy, self.size.z());
+        rec.set_tangents(dpdu, dpdv);
+        Some(rec)
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        let top = self.mips.len() - 1;
+        self.node_box(top, 0, 0).hit_interval(r, ray_tmin, ray_tmax)?;
+        let mut closest = None;
+        self.traverse(r, (top, 0, 0), ray_tmin, ray_tmax, &mut closest);
+        closest
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let top = self.mips.len() - 1;
+        self.node_box(top, 0, 0)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_under_raised_terrain_miss_its_bounds() {
+        let material = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))));
+        let heights = vec![0.5, 0.6, 0.7, 0.8, 0.9, 1.0, 0.7, 0.6, 0.5];
+        let terrain = Heightfield::new(heights, 3, 3, Point3D::new(0.0, 0.0, 0.0), Point3D::new(4.0, 2.0, 4.0), material);
+        let below = Ray::new(Point3D::new(-1.0, 0.9, 2.0), Point3D::new(1.0, 0.0, 0.0));
+        assert!(terrain.bounding_box().hit_interval(&below, 0.0, f64::INFINITY).is_none());
+        let through = Ray::new(Point3D::new(-1.0, 1.7, 2.0), Point3D::new(1.0, 0.0, 0.0));
+        assert!(terrain.hit(&through, 0.0, f64::INFINITY).is_some());
+    }
+}
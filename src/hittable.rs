@@ -1,18 +1,67 @@
 use crate::ray::*;
 use crate::point3d::*;
-use std::rc::Rc;
 use crate::material::*;
+use crate::aabb::*;
+use crate::onb::*;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3D,
     pub normal: Point3D,
     pub t: f64,
     pub front_face: bool,
     pub material: Material,
+    pub u: f64, // Surface coordinates of the hit point, each in [0, 1] except on unbounded planes
+    pub v: f64,
+    pub dpdu: Point3D, // How the hit point moves with u and v along the surface, or zero if unknown
+    pub dpdv: Point3D,
+    pub object_id: usize, // One-based position in the world list of the object hit, or 0 if unknown
+    pub distance: f64, // From the origin of the ray as set by the world list, or t if unknown
 }
 
 pub trait Hittable: Sync {
     fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord>;
+    fn bounding_box(&self) -> Aabb;
+
+    // Solid angle density of random() picking the given direction from origin.
+    fn pdf_value(&self, _origin: &Point3D, _direction: &Point3D) -> f64 {
+        0.0
+    }
+
+    // A random direction from origin towards this object, for sampling it as a light.
+    fn random(&self, _origin: &Point3D) -> Point3D {
+        Point3D::new(1.0, 0.0, 0.0)
+    }
+
+    // A point picked uniformly over the surface, with its outward normal, for sampling light
+    // leaving this object.
+    fn sample_surface(&self) -> Option<HitRecord> {
+        None
+    }
+
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    // Adds every emissive object that can be sampled as a light to lights.
+    fn collect_lights<'a>(&'a self, _lights: &mut Vec<&'a dyn Hittable>) {}
+
+    // Adds the material of every surface of this object to materials, in a fixed order.
+    fn collect_materials(&self, _materials: &mut Vec<Material>) {}
+}
+
+// A closed region of space along a ray, from where the ray enters a solid to where it leaves it.
+// Both boundary records carry the outward surface normal; front_face is resolved by the caller.
+#[derive(Clone)]
+pub struct Span {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+// A closed hittable that can report every interval along the (infinite) ray line in which the
+// ray is inside it, sorted by t. Needed by constructive solid geometry.
+pub trait Solid: Hittable {
+    fn spans(&self, r: &Ray) -> Vec<Span>;
 }
 
 pub struct HittableList {
@@ -20,8 +69,52 @@ pub struct HittableList {
 }
 
 impl HitRecord {
-    pub fn new(p: Point3D, normal: Point3D, t: f64, front_face: bool, material: Material) -> HitRecord {
-        HitRecord{ p, normal, t, front_face, material }
+    pub fn new(p: Point3D, normal: Point3D, t: f64, front_face: bool, material: Material, u: f64, v: f64) -> HitRecord {
+        let zero = Point3D::new(0.0, 0.0, 0.0);
+        HitRecord{ p, normal, t, front_face, material, u, v, dpdu: zero, dpdv: zero, object_id: 0, distance: t }
+    }
+
+    pub fn set_tangents(&mut self, dpdu: Point3D, dpdv: Point3D) {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+    }
+
+    // Basis around the normal with u along the direction u grows in, where the surface knows it.
+    pub fn tangent_frame(&self) -> Onb {
+        Onb::with_tangent(&self.normal, &self.dpdu)
+    }
+
+    // Whether the ray passes through a hole in the material here, which every shape checks
+    // before reporting a hit so that the ray goes on to the next one.
+    pub fn is_cut_out(&self, r: &Ray) -> bool {
+        self.material.is_cut_out(r, self.u, self.v)
+    }
+
+    pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Point3D) {
+        // Make the stored normal point against the incident ray.
+        self.front_face = r.direction().dot(&outward_normal) < 0.0;
+        self.normal = if self.front_face { outward_normal } else { -outward_normal };
+    }
+}
+
+impl Span {
+    pub fn new(enter: HitRecord, exit: HitRecord) -> Span {
+        Span { enter, exit }
+    }
+
+    // Returns the first boundary of any span inside (ray_tmin, ray_tmax) that is not cut out,
+    // with its normal oriented against the ray.
+    pub fn first_hit(spans: &[Span], r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        for span in spans.iter() {
+            for boundary in [&span.enter, &span.exit] {
+                if boundary.t > ray_tmin && boundary.t < ray_tmax && !boundary.is_cut_out(r) {
+                    let mut rec = boundary.clone();
+                    rec.set_face_normal(r, boundary.normal);
+                    return Some(rec);
+                }
+            }
+        }
+        None
     }
 }
 
@@ -35,12 +128,30 @@ impl Hittable for HittableList {
     fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
         let mut closest_so_far = ray_tmax;
         let mut hit_anything: Option<HitRecord> = None;
-        for hittable in self.list.iter() {
-            if let Some(hit) = hittable.hit(r, ray_tmin, closest_so_far) {
+        for (index, hittable) in self.list.iter().enumerate() {
+            if let Some(mut hit) = hittable.hit(r, ray_tmin, closest_so_far) {
                 closest_so_far = hit.t;
+                hit.object_id = index + 1;
+                hit.distance = hit.t * r.direction().length();
                 hit_anything = Some(hit);
             }
         }
         hit_anything
     }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        for hittable in self.list.iter() {
+            hittable.collect_lights(lights);
+        }
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        for hittable in self.list.iter() {
+            hittable.collect_materials(materials);
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.list.iter().fold(Aabb::empty(), |bbox, hittable| bbox.surrounding(&hittable.bounding_box()))
+    }
 }
@@ -0,0 +1,191 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::random::*;
+use crate::color::*;
+use crate::debug::*;
+use crate::film::*;
+use crate::bdpt::*;
+use crate::photon::*;
+use crate::mlt::*;
+use crate::spectrum::*;
+use crate::subsurface::*;
+use rayon::prelude::*;
+
+// A light transport algorithm, producing the linear color of every pixel in row-major order.
+pub trait Integrator: Sync {
+    fn render(&self, camera: &Camera, world: &dyn Hittable) -> Vec<Point3D>;
+
+    // The image along with the variance of each pixel's estimate of luminance, for integrators
+    // that can tell it.
+    fn render_with_variance(&self, camera: &Camera, world: &dyn Hittable) -> (Vec<Point3D>, Option<Vec<f64>>) {
+        (self.render(camera, world), None)
+    }
+}
+
+// An integrator that estimates the light arriving along each camera ray on its own. Light found
+// to reach other pixels can be splatted onto the film.
+pub trait SampleIntegrator: Sync {
+    fn li(&self, r: &Ray, camera: &Camera, world: &dyn Hittable, lights: &LightList, film: &Film) -> Point3D;
+
+    // Whether li can trace light by wavelength when the camera asks for it, turning what it
+    // splats into RGB itself.
+    fn supports_spectral(&self) -> bool {
+        false
+    }
+}
+
+impl<T: SampleIntegrator> Integrator for T {
+    fn render(&self, camera: &Camera, world: &dyn Hittable) -> Vec<Point3D> {
+        self.render_with_variance(camera, world).0
+    }
+
+    // Averages the camera's samples in each pixel, plus whatever was splatted onto the pixel for
+    // as many samples. The variance leaves out the splats.
+    fn render_with_variance(&self, camera: &Camera, world: &dyn Hittable) -> (Vec<Point3D>, Option<Vec<f64>>) {
+        let stats = camera.stats();
+        let (width, height) = (stats.width() as usize, stats.height() as usize);
+        let samples_per_pixel = stats.samples_per_pixel();
+        let n = samples_per_pixel as f64;
+        let lights = LightList::new(world);
+        let film = Film::new(width, height);
+        let spectral = camera.spectral() && self.supports_spectral();
+        let pixels: Vec<(Point3D, f64)> = (0..width * height).into_par_iter().map(|index| {
+            let (w, h) = (index % width, index / width);
+            let mut pixel_color = Point3D::new(0.0, 0.0, 0.0);
+            let mut sum_squares = 0.0; // Of the luminance of each sample
+            for _ in 0..samples_per_pixel {
+                let r = camera.get_ray(w as f64, h as f64);
+                if spectral {
+                    set_wavelengths(Some(Wavelengths::sample(random_f64())));
+                }
+                let sample = to_rgb(self.li(&r, camera, world, &lights, &film));
+                set_wavelengths(None);
+                pixel_color = pixel_color + sample;
+                sum_squares += luminance(&sample) * luminance(&sample);
+            }
+            let mean = luminance(&pixel_color) / n;
+            let variance = if samples_per_pixel > 1 { (sum_squares - n * mean * mean).max(0.0) / ((n - 1.0) * n) } else { 0.0 };
+            (pixel_color, variance)
+        }).collect();
+        let colors = pixels.iter().zip(film.splats().iter()).map(|((pixel, _), splat)| (*pixel + *splat) / n).collect();
+        (colors, Some(pixels.iter().map(|(_, variance)| *variance).collect()))
+    }
+}
+
+// Unidirectional path tracing with next-event estimation.
+pub struct PathIntegrator;
+
+impl SampleIntegrator for PathIntegrator {
+    fn li(&self, r: &Ray, camera: &Camera, world: &dyn Hittable, lights: &LightList, _film: &Film) -> Point3D {
+        let mut radiance = Point3D::new(0.0, 0.0, 0.0);
+        let mut throughput = Point3D::new(1.0, 1.0, 1.0); // Fraction of light at the current vertex that reaches the camera
+        let mut ray = *r;
+        let mut walk: Option<Walk> = None; // Through a translucent object, when the path is on one
+        // Density with which the previous bounce picked ray, or None if it was the camera ray or
+        // a specular bounce that light sampling could not have produced.
+        let mut bsdf_pdf: Option<f64> = None;
+
+        // No more light is gathered past max_depth bounces, and from min_depth on Russian
+        // roulette may end the path sooner.
+        for depth in 0..camera.stats().max_depth() {
+            let rec = match hit_on_walk(world, &ray, walk.as_ref(), 0.001, f64::MAX) {
+                Some(rec) => rec,
+                None => {
+                    radiance = radiance + throughput * camera.background_color(&ray);
+                    break;
+                }
+            };
+
+            // Light sampling at the previous bounce may also have found this emitter.
+            let mut emitted = Scatterable::emitted(&rec.material, &ray, &rec);
+            if let Some(pdf) = bsdf_pdf {
+                emitted = emitted * power_heuristic(pdf, lights.pdf_value(&ray.origin(), &ray.direction()));
+            }
+            radiance = radiance + throughput * emitted;
+
+            let sample = match Scatterable::scatter(&rec.material, &ray, &rec) {
+                Some(sample) => sample,
+                None => break,
+            };
+            if !sample.specular {
+                radiance = radiance + throughput * sample_lights(&ray, &rec, world, lights);
+            }
+            throughput = throughput * sample.weight(&rec.normal);
+            bsdf_pdf = if sample.specular { None } else { Some(sample.pdf) };
+            walk = sample.walk;
+            ray = Ray::new(rec.p, sample.direction);
+
+            // Russian roulette: end dim paths at random, boosting the survivors to compensate.
+            if depth + 1 >= camera.stats().min_depth() {
+                let survival = throughput.max_component().min(1.0);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+        radiance
+    }
+
+    fn supports_spectral(&self) -> bool {
+        true
+    }
+}
+
+// Direct light at a non-specular hit from a shadow ray towards a randomly picked light.
+pub fn sample_lights(r: &Ray, rec: &HitRecord, world: &dyn Hittable, lights: &LightList) -> Point3D {
+    if lights.is_empty() {
+        return Point3D::new(0.0, 0.0, 0.0);
+    }
+    let shadow_ray = Ray::new(rec.p, lights.random(&rec.p));
+    let wi = shadow_ray.direction().unit_vector();
+    let wo = -r.direction().unit_vector();
+    let light_pdf = lights.pdf_value(&rec.p, &wi);
+    let bsdf_pdf = Scatterable::pdf(&rec.material, rec, &wi, &wo);
+    if light_pdf <= 0.0 || bsdf_pdf <= 0.0 {
+        return Point3D::new(0.0, 0.0, 0.0);
+    }
+    match world.hit(&shadow_ray, 0.001, f64::MAX) {
+        Some(light_rec) => {
+            let emitted = Scatterable::emitted(&light_rec.material, &shadow_ray, &light_rec);
+            let f = Scatterable::eval(&rec.material, rec, &wi, &wo);
+            emitted * f * (rec.normal.dot(&wi).abs() * power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
+        }
+        None => Point3D::new(0.0, 0.0, 0.0),
+    }
+}
+
+// Looks up an integrator by the name used to pick it on the command line. MLT optionally takes
+// its mutation size and large step probability after colons, as in mlt:0.02:0.5.
+pub fn integrator_from_name(name: &str) -> Option<Box<dyn Integrator>> {
+    if let Some(params) = name.strip_prefix("mlt:") {
+        let params: Vec<f64> = params.split(':').map(|param| param.parse().ok()).collect::<Option<_>>()?;
+        let mut mlt = MltIntegrator::new(100_000, 1000, 100);
+        match params[..] {
+            [sigma] if sigma > 0.0 => mlt.set_sigma(sigma),
+            [sigma, large_step_probability] if sigma > 0.0 && (0.0..=1.0).contains(&large_step_probability) => {
+                mlt.set_sigma(sigma);
+                mlt.set_large_step_probability(large_step_probability);
+            }
+            _ => return None,
+        }
+        return Some(Box::new(mlt));
+    }
+    match name {
+        "path" => Some(Box::new(PathIntegrator)),
+        "normals" => Some(Box::new(NormalsIntegrator)),
+        "depth" => Some(Box::new(DepthIntegrator::new(20.0))),
+        "albedo" => Some(Box::new(AlbedoIntegrator)),
+        "ao" => Some(Box::new(AmbientOcclusionIntegrator::new(16, 1.0))),
+        "heatmap" => Some(Box::new(BounceHeatmapIntegrator)),
+        "bdpt" => Some(Box::new(BdptIntegrator)),
+        "photons" => Some(Box::new(PhotonMapIntegrator::new(200_000, 0.05))),
+        "sppm" => Some(Box::new(SppmIntegrator::new(20_000, 0.1, 0.7))),
+        "mlt" => Some(Box::new(MltIntegrator::new(100_000, 1000, 100))),
+        _ => None,
+    }
+}
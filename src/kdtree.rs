@@ -0,0 +1,131 @@
+use crate::point3d::*;
+
+// Light carried to a surface point by one path traced from a light.
+#[derive(Clone, Copy)]
+pub struct Photon {
+    pub p: Point3D,
+    pub wi: Point3D, // Unit vector back towards where the photon came from
+    pub power: Point3D,
+}
+
+// A balanced kd-tree over photons, kept implicitly in one array: each subrange is split at its
+// middle photon, with the photons below the split plane before it and those above after it.
+pub struct KdTree {
+    photons: Vec<Photon>,
+    axes: Vec<usize>, // Split axis of the subrange each photon is the middle of
+}
+
+impl KdTree {
+    pub fn new(photons: Vec<Photon>) -> KdTree {
+        let mut tree = KdTree { axes: vec![0; photons.len()], photons };
+        let len = tree.photons.len();
+        tree.build(0, len);
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    fn build(&mut self, start: usize, end: usize) {
+        if end - start <= 1 {
+            return;
+        }
+        // Split across the widest extent of the subrange.
+        let (mut low, mut high) = ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]);
+        for photon in self.photons[start..end].iter() {
+            for axis in 0..3 {
+                low[axis] = low[axis].min(photon.p.axis(axis));
+                high[axis] = high[axis].max(photon.p.axis(axis));
+            }
+        }
+        let axis = (0..3).max_by(|a, b| (high[*a] - low[*a]).total_cmp(&(high[*b] - low[*b]))).unwrap_or(0);
+
+        let middle = (start + end) / 2;
+        self.photons[start..end].select_nth_unstable_by(middle - start, |a, b| a.p.axis(axis).total_cmp(&b.p.axis(axis)));
+        self.axes[middle] = axis;
+        self.build(start, middle);
+        self.build(middle + 1, end);
+    }
+
+    // Calls found with every photon closer to p than radius.
+    pub fn for_each_within<F: FnMut(&Photon)>(&self, p: &Point3D, radius: f64, found: &mut F) {
+        self.search(0, self.len(), p, radius, found);
+    }
+
+    fn search<F: FnMut(&Photon)>(&self, start: usize, end: usize, p: &Point3D, radius: f64, found: &mut F) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let photon = &self.photons[middle];
+        if (photon.p - *p).length_squared() < radius * radius {
+            found(photon);
+        }
+        if end - start == 1 {
+            return;
+        }
+        // Search the side of the split plane p is on, and the other side only if the sphere
+        // around p reaches across.
+        let axis = self.axes[middle];
+        let offset = p.axis(axis) - photon.p.axis(axis);
+        let (near, far) = if offset < 0.0 { ((start, middle), (middle + 1, end)) } else { ((middle + 1, end), (start, middle)) };
+        self.search(near.0, near.1, p, radius, found);
+        if offset.abs() < radius {
+            self.search(far.0, far.1, p, radius, found);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::*;
+
+    fn random_photons(count: usize) -> Vec<Photon> {
+        (0..count).map(|i| Photon {
+            p: Point3D::new(random_f64(), random_f64() * 2.0, random_f64() * 0.5),
+            wi: Point3D::new(0.0, 1.0, 0.0),
+            power: Point3D::new(i as f64, 0.0, 0.0), // Tells the photons apart
+        }).collect()
+    }
+
+    fn found_ids(tree: &KdTree, p: &Point3D, radius: f64) -> Vec<usize> {
+        let mut ids = Vec::new();
+        tree.for_each_within(p, radius, &mut |photon| ids.push(photon.power.x() as usize));
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn radius_query_matches_brute_force() {
+        let photons = random_photons(2000);
+        let tree = KdTree::new(photons.clone());
+        assert_eq!(tree.len(), photons.len());
+        for _ in 0..200 {
+            let p = Point3D::new(random_f64() * 1.2 - 0.1, random_f64() * 2.2 - 0.1, random_f64() * 0.7 - 0.1);
+            let radius = random_f64() * 0.3;
+            let expected: Vec<usize> = photons.iter()
+                .filter(|photon| (photon.p - p).length_squared() < radius * radius)
+                .map(|photon| photon.power.x() as usize)
+                .collect();
+            assert_eq!(found_ids(&tree, &p, radius), expected);
+        }
+    }
+
+    #[test]
+    fn queries_on_small_trees() {
+        let tree = KdTree::new(Vec::new());
+        assert!(tree.is_empty());
+        assert!(found_ids(&tree, &Point3D::new(0.0, 0.0, 0.0), 1.0).is_empty());
+
+        let photons = random_photons(2);
+        let tree = KdTree::new(photons.clone());
+        assert_eq!(found_ids(&tree, &photons[1].p, 1e-9), vec![1]);
+        assert_eq!(found_ids(&tree, &photons[0].p, 10.0), vec![0, 1]);
+    }
+}
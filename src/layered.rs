@@ -0,0 +1,251 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::light::*;
+use crate::random::*;
+use crate::spectrum::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// A thin dielectric coat over another material, like the clear coat of car paint or the varnish
+// on wood. Light refracts into the coat, is absorbed on its way through, scatters off the base
+// and may bounce between the base and the underside of the coat several times before it leaves.
+// The BSDF of the stack has no closed form, so eval estimates it without bias by following one
+// random path through the layers, in the position-free way of Guo et al. 2018 (as in PBRT v4),
+// and pdf is an approximation of the density scatter picks directions with, good enough for
+// multiple importance sampling. The approximation follows random paths too, so pdf draws them
+// from a hash of its arguments: asked twice for the same pair of directions at the same point it
+// gives the same answer, as the MIS weights of the strategies meeting there need.
+//
+// Only reflection off the front face is modelled: light that passes through the base leaves the
+// stack in a sampled direction that no other strategy can reproduce, and hits on the back face
+// see the bare base.
+#[derive(Debug, Clone)]
+pub struct Coated {
+    pub base: Arc<Material>,
+    pub interface: RoughDielectric, // Top boundary of the coat, without absorption
+    pub thickness: f64,
+    pub absorption: Point3D, // Fraction of each channel absorbed per unit of distance in the coat
+}
+
+// Longest path followed through the layers.
+const MAX_DEPTH: usize = 10;
+
+impl Coated {
+    pub fn new(base: Material, refraction_index: f64, roughness: f64, thickness: f64, absorption: Point3D) -> Coated {
+        let interface = RoughDielectric::new(refraction_index, roughness, Point3D::new(0.0, 0.0, 0.0));
+        Coated { base: Arc::new(base), interface, thickness, absorption }
+    }
+
+    // Light surviving a crossing of the coat in direction w.
+    fn transmittance(&self, rec: &HitRecord, w: &Point3D) -> Point3D {
+        let distance = self.thickness / rec.normal.dot(w).abs().max(1e-6);
+        let absorption = from_rgb(self.absorption);
+        Point3D::new((-absorption.x() * distance).exp(), (-absorption.y() * distance).exp(), (-absorption.z() * distance).exp())
+    }
+
+    // The surfaces of the stack as seen from each side, all at the hit point.
+    fn top_from_above(&self, rec: &HitRecord) -> HitRecord {
+        HitRecord { material: Material::RoughDielectric(self.interface), front_face: true, ..*rec }
+    }
+
+    fn top_from_below(&self, rec: &HitRecord) -> HitRecord {
+        HitRecord { material: Material::RoughDielectric(self.interface), front_face: false, normal: -rec.normal, ..*rec }
+    }
+
+    fn bottom(&self, rec: &HitRecord) -> HitRecord {
+        HitRecord { material: (*self.base).clone(), front_face: true, ..*rec }
+    }
+
+    // Samples a direction at surface for light arriving from wo, on the side of its normal,
+    // keeping only reflections or only transmissions. Also returns the unit direction.
+    fn sample(surface: &HitRecord, wo: &Point3D, reflected: bool) -> Option<(ScatterSample, Point3D)> {
+        let sample = Scatterable::scatter(&surface.material, &Ray::new(surface.p + *wo, -*wo), surface)?;
+        let direction = sample.direction.unit_vector();
+        let stays = direction.dot(&surface.normal) > 0.0;
+        if stays == reflected && sample.pdf > 0.0 { Some((sample, direction)) } else { None }
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        if !rec.front_face {
+            return Scatterable::scatter(self.base.as_ref(), r_in, rec);
+        }
+        let (top, below, bottom) = (self.top_from_above(rec), self.top_from_below(rec), self.bottom(rec));
+        let wo = -r_in.direction().unit_vector();
+
+        // Walk through the stack until the light leaves it through either side.
+        let sample = Scatterable::scatter(&top.material, r_in, &top)?;
+        let mut beta = sample.weight(&rec.normal);
+        let mut w = sample.direction.unit_vector();
+        let mut specular = sample.specular;
+        let mut at_bottom = false;
+        let mut escaped = w.dot(&rec.normal) > 0.0;
+        for depth in 0..MAX_DEPTH {
+            if escaped {
+                break;
+            }
+            if depth > 3 && beta.max_component() < 0.25 {
+                let survival = beta.max_component();
+                if random_f64() >= survival {
+                    return None;
+                }
+                beta = beta / survival;
+            }
+
+            // Cross the coat, then scatter off the surface on the other side.
+            beta = beta * self.transmittance(rec, &w);
+            at_bottom = !at_bottom;
+            let surface = if at_bottom { &bottom } else { &below };
+            let sample = Scatterable::scatter(&surface.material, &Ray::new(rec.p - w, w), surface)?;
+            beta = beta * sample.weight(&rec.normal);
+            specular &= sample.specular;
+            w = sample.direction.unit_vector();
+            let upwards = w.dot(&rec.normal) > 0.0;
+            if at_bottom && !upwards {
+                // Through the base and out of the stack.
+                return Some(ScatterSample::new(w, beta, 1.0, true));
+            }
+            escaped = !at_bottom && upwards;
+        }
+        if !escaped {
+            return None;
+        }
+
+        // A path of mirror-like bounces only can be reproduced by no other strategy.
+        let pdf = self.pdf(rec, &w, &wo);
+        if specular || pdf <= 0.0 {
+            return Some(ScatterSample::new(w, beta, 1.0, true));
+        }
+        Some(ScatterSample::new(w, beta * (pdf / w.dot(&rec.normal)), pdf, false))
+    }
+
+    pub fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        if !rec.front_face {
+            return Scatterable::eval(self.base.as_ref(), rec, wi, wo);
+        }
+        let zero = Point3D::new(0.0, 0.0, 0.0);
+        if wi.dot(&rec.normal) <= 0.0 || wo.dot(&rec.normal) <= 0.0 {
+            return zero;
+        }
+        let (top, below, bottom) = (self.top_from_above(rec), self.top_from_below(rec), self.bottom(rec));
+
+        // Reflection off the top, plus an estimate of the light that enters the coat along wo and
+        // leaves it along wi. Into the coat from wo, and, for connecting to wi from inside, from wi.
+        let mut f = Scatterable::eval(&top.material, &top, wi, wo);
+        let (entry, mut w) = match Coated::sample(&top, wo, false) {
+            Some(sample) => sample,
+            None => return f,
+        };
+        let exit = Coated::sample(&top, wi, false);
+        let mut beta = entry.weight(&rec.normal);
+        let mut at_bottom = false;
+        for depth in 0..MAX_DEPTH {
+            if depth > 3 && beta.max_component() < 0.25 {
+                let survival = beta.max_component();
+                if random_f64() >= survival {
+                    break;
+                }
+                beta = beta / survival;
+            }
+            beta = beta * self.transmittance(rec, &w);
+            at_bottom = !at_bottom;
+
+            if !at_bottom {
+                // Back down off the underside of the coat.
+                let (sample, direction) = match Coated::sample(&below, &-w, true) {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                beta = beta * sample.weight(&rec.normal);
+                w = direction;
+                continue;
+            }
+
+            // At the base: connect to the direction that refracts out along wi...
+            if let Some((exit, exit_direction)) = exit {
+                let towards_exit = -exit_direction;
+                let f_base = Scatterable::eval(self.base.as_ref(), &bottom, &towards_exit, &-w);
+                if f_base.max_component() > 0.0 {
+                    let weight = if exit.specular { 1.0 } else { power_heuristic(exit.pdf, Scatterable::pdf(self.base.as_ref(), &bottom, &towards_exit, &-w)) };
+                    f = f + beta * f_base * exit.weight(&rec.normal) * self.transmittance(rec, &towards_exit) * weight;
+                }
+            }
+
+            // ...then scatter off the base and connect through the coat to wi from there.
+            let (sample, direction) = match Coated::sample(&bottom, &-w, true) {
+                Some(sample) => sample,
+                None => break,
+            };
+            beta = beta * sample.weight(&rec.normal);
+            w = direction;
+            let f_exit = Scatterable::eval(&below.material, &below, wi, &-w);
+            if f_exit.max_component() > 0.0 {
+                let weight = if sample.specular { 1.0 } else { power_heuristic(sample.pdf, Scatterable::pdf(&top.material, &top, &-w, wi)) };
+                f = f + beta * self.transmittance(rec, &w) * f_exit * weight;
+            }
+        }
+        f
+    }
+
+    pub fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        if !rec.front_face {
+            return Scatterable::pdf(self.base.as_ref(), rec, wi, wo);
+        }
+        if wi.dot(&rec.normal) <= 0.0 || wo.dot(&rec.normal) <= 0.0 {
+            return 0.0;
+        }
+        let (top, below, bottom) = (self.top_from_above(rec), self.top_from_below(rec), self.bottom(rec));
+
+        // Reflection off the top, plus one estimate of the paths in through wo, off the base and
+        // out through wi, mixed with a uniform density since the estimate misses longer paths.
+        let mut pdf = Scatterable::pdf(&top.material, &top, wi, wo);
+        let seed = hash_f64(&[wi.x(), wi.y(), wi.z(), wo.x(), wo.y(), wo.z(), rec.p.x(), rec.p.y(), rec.p.z()]);
+        let mut draws = 0.0;
+        let draw = Box::new(move || {
+            draws += 1.0;
+            hash_f64(&[seed, draws])
+        });
+        pdf += with_sample_source(draw, || self.through_base_pdf(&top, &below, &bottom, wi, wo));
+        0.1 / (4.0 * PI) + 0.9 * pdf
+    }
+
+    // The estimate of the paths off the base for pdf.
+    fn through_base_pdf(&self, top: &HitRecord, below: &HitRecord, bottom: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        let mut pdf = 0.0;
+        if let (Some((_, entry)), Some((exit, exit_direction))) = (Coated::sample(top, wo, false), Coated::sample(top, wi, false)) {
+            if self.interface.distribution.effectively_smooth() {
+                pdf += Scatterable::pdf(self.base.as_ref(), bottom, &-exit_direction, &-entry);
+            } else if let Some((sample, direction)) = Coated::sample(bottom, &-entry, true) {
+                let top_pdf = Scatterable::pdf(&below.material, below, wi, &-direction);
+                if sample.specular {
+                    pdf += top_pdf;
+                } else {
+                    let base_pdf = Scatterable::pdf(self.base.as_ref(), bottom, &-exit_direction, &-entry);
+                    pdf += power_heuristic(exit.pdf, base_pdf) * base_pdf + power_heuristic(sample.pdf, top_pdf) * top_pdf;
+                }
+            }
+        }
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::*;
+
+    #[test]
+    fn pdf_is_the_same_when_asked_again() {
+        let base = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))));
+        let coated = Coated::new(base, 1.5, 0.3, 0.05, Point3D::new(0.5, 0.5, 0.5));
+        let normal = Point3D::new(0.0, 0.0, 1.0);
+        let rec = HitRecord::new(Point3D::new(0.3, -0.2, 0.0), normal, 1.0, true, Material::Coated(coated.clone()), 0.0, 0.0);
+        let (wi, wo) = (Point3D::new(0.6, 0.0, 0.8), Point3D::new(-0.28, 0.0, 0.96));
+        let pdf = coated.pdf(&rec, &wi, &wo);
+        assert!(pdf > 0.0);
+        for _ in 0..10 {
+            assert_eq!(coated.pdf(&rec, &wi, &wo), pdf);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::point3d::*;
+use crate::hittable::*;
+use crate::random::*;
+use crate::ray::*;
+
+// The emissive objects of a scene, sampled as a uniform mixture for next-event estimation and
+// for starting light paths.
+pub struct LightList<'a> {
+    lights: Vec<&'a dyn Hittable>,
+}
+
+impl<'a> LightList<'a> {
+    pub fn new(world: &'a dyn Hittable) -> LightList<'a> {
+        let mut lights = Vec::new();
+        world.collect_lights(&mut lights);
+        LightList { lights }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn pdf_value(&self, origin: &Point3D, direction: &Point3D) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.lights.iter().map(|light| light.pdf_value(origin, direction)).sum();
+        sum / self.lights.len() as f64
+    }
+
+    pub fn random(&self, origin: &Point3D) -> Point3D {
+        self.pick().random(origin)
+    }
+
+    // A point on a randomly picked light, and the area density of picking it among all lights.
+    pub fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let light = self.pick();
+        let rec = light.sample_surface()?;
+        Some((rec, 1.0 / (self.lights.len() as f64 * light.area())))
+    }
+
+    // Area density of sample_surface picking p, the first point of a light seen from origin.
+    pub fn pdf_surface(&self, origin: &Point3D, p: &Point3D) -> f64 {
+        let r = Ray::new(*origin, *p - *origin);
+        let sum: f64 = self.lights.iter()
+            .filter(|light| light.hit(&r, 0.001, f64::MAX).is_some_and(|rec| (rec.t - 1.0).abs() < 1e-6))
+            .map(|light| 1.0 / light.area())
+            .sum();
+        sum / self.lights.len().max(1) as f64
+    }
+
+    fn pick(&self) -> &'a dyn Hittable {
+        let index = ((random_f64() * self.lights.len() as f64) as usize).min(self.lights.len() - 1);
+        self.lights[index]
+    }
+}
+
+// Multiple importance sampling weight for a sample drawn with pdf_a, when pdf_b could also have
+// produced it.
+pub fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let a2 = pdf_a * pdf_a;
+    let b2 = pdf_b * pdf_b;
+    if a2 + b2 == 0.0 { 0.0 } else { a2 / (a2 + b2) }
+}
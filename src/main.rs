@@ -6,6 +6,34 @@ mod sphere;
 mod camera;
 mod random;
 mod material;
+mod csg;
+mod sdf;
+mod aabb;
+mod quadric;
+mod torus;
+mod texture;
+mod plane;
+mod heightfield;
+mod light;
+mod onb;
+mod microfacet;
+mod principled;
+mod layered;
+mod integrator;
+mod debug;
+mod film;
+mod bdpt;
+mod kdtree;
+mod photon;
+mod mlt;
+mod aov;
+mod denoise;
+mod tonemap;
+mod spectrum;
+mod thinfilm;
+mod subsurface;
+mod bump;
+mod mask;
 
 use crate::point3d::*;
 use crate::hittable::*;
@@ -13,17 +41,36 @@ use crate::sphere::*;
 use crate::camera::*;
 use crate::material::*;
 use crate::random::*;
+use crate::csg::*;
+use crate::sdf::*;
+use crate::quadric::*;
+use crate::torus::*;
+use crate::texture::*;
+use crate::plane::*;
+use crate::principled::*;
+use crate::layered::*;
+use crate::heightfield::*;
+use crate::integrator::*;
+use crate::aov::*;
+use crate::denoise::*;
+use crate::tonemap::*;
+use crate::spectrum::*;
+use crate::thinfilm::*;
+use crate::subsurface::*;
+use crate::bump::*;
+use crate::mask::*;
+use std::f64::consts::PI;
 
 fn three_balls() -> HittableList {
     let mut world = HittableList::new(Vec::new());
 
-    let material_ground = Material::Lambertian(Lambertian{ albedo: Point3D::new(0.8, 0.8, 0.0) });
-    let material_center = Material::Lambertian(Lambertian{ albedo: Point3D::new(0.1, 0.2, 0.5) });
-    let material_left = Material::Dielectric(Dielectric{ refraction_index: 1.50 });
-    let material_bubble = Material::Dielectric(Dielectric{ refraction_index: 1.00 / 1.50 }); 
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.8, 0.8, 0.0)) });
+    let material_center = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.1, 0.2, 0.5)) });
+    let material_left = Material::Dielectric(Dielectric::new(1.50));
+    let material_bubble = Material::Dielectric(Dielectric::new(1.00 / 1.50)); 
     let material_right = Material::Metal(Metal{ albedo: Point3D::new(0.8, 0.6, 0.2), fuzz: 1.0 });
 
-    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, -100.5, -1.0), 100.0, material_ground)));
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
     world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
     world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
     world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.4, material_bubble)));
@@ -32,11 +79,423 @@ fn three_balls() -> HittableList {
     world
 }
 
+fn csg_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.8, 0.8, 0.0))));
+    let material_lens = Material::Dielectric(Dielectric::new(1.50));
+    let material_bowl = Material::Metal(Metal::new(Point3D::new(0.8, 0.6, 0.2), 0.3));
+    let material_inside = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.9, 0.9, 0.9))));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+
+    // A biconvex lens is the overlap of two large spheres.
+    let lens = Csg::intersection(
+        Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -0.2), 1.0, material_lens.clone())),
+        Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.8), 1.0, material_lens.clone())),
+    );
+    world.list.push(Box::new(lens));
+
+    // A bowl is a hollowed-out sphere with its top sliced off.
+    let shell = Csg::difference(
+        Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_bowl)),
+        Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.45, material_inside.clone())),
+    );
+    let bowl = Csg::difference(
+        Box::new(shell),
+        Box::new(Sphere::new(Point3D::new(1.0, 1.0, -1.0), 0.8, material_inside)),
+    );
+    world.list.push(Box::new(bowl));
+
+    // A glass snowman is the union of two spheres, without the faces they would have inside
+    // each other.
+    let snowman = Csg::union(
+        Box::new(Sphere::new(Point3D::new(0.0, -0.28, -1.8), 0.22, material_lens.clone())),
+        Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.8), 0.15, material_lens)),
+    );
+    world.list.push(Box::new(snowman));
+
+    world
+}
+
+fn sdf_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_red = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.7, 0.2, 0.2)) });
+    let material_blue = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.2, 0.3, 0.7)) });
+    let material_gold = Material::Metal(Metal::new(Point3D::new(0.8, 0.6, 0.2), 0.1));
+    let material_glass = Material::Dielectric(Dielectric::new(1.50));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+
+    let rounded_box = RoundedBox { half_extents: Point3D::new(0.3, 0.3, 0.3), radius: 0.08 };
+    world.list.push(Box::new(SdfShape::new(Box::new(rounded_box), Point3D::new(-1.5, -0.2, -1.5), 0.6, material_red.clone())));
+
+    let torus = TorusSdf { major_radius: 0.3, minor_radius: 0.1 };
+    world.list.push(Box::new(SdfShape::new(Box::new(torus), Point3D::new(-0.5, -0.4, -1.0), 0.45, material_gold.clone())));
+
+    let capsule = Capsule { a: Point3D::new(0.0, -0.3, 0.0), b: Point3D::new(0.0, 0.3, 0.0), radius: 0.15 };
+    world.list.push(Box::new(SdfShape::new(Box::new(capsule), Point3D::new(0.4, -0.2, -0.8), 0.5, material_glass)));
+
+    // Two spheres melted together.
+    let blob = SmoothUnion {
+        a: Box::new(Translated { sdf: Box::new(|p: Point3D| p.length() - 0.2), offset: Point3D::new(-0.15, 0.0, 0.0) }),
+        b: Box::new(Translated { sdf: Box::new(|p: Point3D| p.length() - 0.15), offset: Point3D::new(0.2, 0.05, 0.0) }),
+        k: 0.15,
+    };
+    world.list.push(Box::new(SdfShape::new(Box::new(blob), Point3D::new(1.2, -0.3, -1.0), 0.6, material_blue)));
+
+    let sponge = MengerSponge { half_size: 0.4, iterations: 3 };
+    world.list.push(Box::new(SdfShape::new(Box::new(sponge), Point3D::new(0.2, -0.1, -2.5), 0.75, material_red)));
+
+    let bulb = Mandelbulb { power: 8.0, iterations: 12, scale: 0.4 };
+    world.list.push(Box::new(SdfShape::new(Box::new(bulb), Point3D::new(1.6, 0.1, -2.5), 0.55, material_gold)));
+
+    world
+}
+
+fn quadric_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let checker = Checker::new(2.0, Point3D::new(0.2, 0.3, 0.1), Point3D::new(0.9, 0.9, 0.9));
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::Checker(checker) });
+    let material_red = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.7, 0.2, 0.2)) });
+    let material_blue = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.2, 0.3, 0.7)) });
+    let material_steel = Material::Metal(Metal::new(Point3D::new(0.7, 0.7, 0.75), 0.05));
+    let material_gold = Material::Metal(Metal::new(Point3D::new(0.8, 0.6, 0.2), 0.2));
+    let material_glass = Material::Dielectric(Dielectric::new(1.50));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Cylinder::new(Point3D::new(-1.6, -0.5, -1.5), 0.25, 0.6, true, material_red)));
+    world.list.push(Box::new(Cone::new(Point3D::new(-0.8, -0.5, -1.2), 0.3, 0.7, true, material_blue)));
+    world.list.push(Box::new(Paraboloid::new(Point3D::new(0.0, -0.5, -1.6), 0.35, 0.5, false, material_steel)));
+    world.list.push(Box::new(Hyperboloid::new(Point3D::new(0.8, -0.5, -1.2), 0.15, 0.3, 0.7, true, material_glass)));
+    world.list.push(Box::new(Torus::new(Point3D::new(1.6, -0.4, -1.5), 0.3, 0.1, material_gold)));
+
+    world
+}
+
+// Terrain from a grayscale DEM image, or rolling hills without one.
+fn terrain_scene(heightmap: Option<&str>) -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_terrain = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.4, 0.5, 0.3)) });
+    let material_water = Material::Metal(Metal::new(Point3D::new(0.3, 0.4, 0.6), 0.05));
+
+    let origin = Point3D::new(-5.0, -0.5, -8.0);
+    let size = Point3D::new(10.0, 1.5, 10.0);
+    let terrain = heightmap.and_then(|path| match Heightfield::from_png(path, origin, size, material_terrain.clone()) {
+        Ok(terrain) => Some(terrain),
+        Err(e) => {
+            eprintln!("Cannot read heightmap '{}' ({}), using rolling hills", path, e);
+            None
+        }
+    }).unwrap_or_else(|| {
+        // A few octaves of sine waves.
+        let (width, depth) = (256, 256);
+        let heights = (0..width * depth).map(|k| {
+            let x = (k % width) as f64 / width as f64;
+            let z = (k / width) as f64 / depth as f64;
+            let mut h = 0.0;
+            for octave in 0..4 {
+                let f = (2 << octave) as f64;
+                h += ((x * f * 3.1 + z * 1.7).sin() * (z * f * 2.3 - x * 0.9).cos() + 1.0) / (4 << octave) as f64;
+            }
+            h
+        }).collect();
+        Heightfield::new(heights, width, depth, origin, size, material_terrain)
+    });
+    world.list.push(Box::new(terrain));
+    world.list.push(Box::new(Plane::ground(-0.2, material_water)));
+
+    world
+}
+
+// The material of a lamp made from a spectrum or an output, or a dark one in its place when they
+// give no light to make it from.
+fn lamp_or_dark(name: &str, lamp: Option<DiffuseLight>) -> Material {
+    Material::DiffuseLight(lamp.unwrap_or_else(|| {
+        eprintln!("The {} gives off no visible light, leaving it dark", name);
+        DiffuseLight::new(Point3D::new(0.0, 0.0, 0.0))
+    }))
+}
+
+fn lights_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_center = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.1, 0.2, 0.5)) });
+    let material_left = Material::Dielectric(Dielectric::new(1.50));
+    let material_right = Material::Metal(Metal::new(Point3D::new(0.8, 0.6, 0.2), 0.3));
+    // A warm white floodlight of 10 cm radius, given by its output in watts.
+    let lamp_area = 4.0 * PI * 0.1 * 0.1;
+    let material_lamp = lamp_or_dark("floodlight", DiffuseLight::from_watts(Spectrum::Rgb(Point3D::new(1.0, 0.9, 0.75)), 6800.0, lamp_area));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_right)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn metals_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_center = Material::Conductor(Conductor::copper(0.4));
+    let material_left = Material::Conductor(Conductor::silver(0.0));
+    let material_right = Material::Conductor(Conductor::gold(0.2));
+    let mut brushed_aluminium = Conductor::aluminium(0.1);
+    brushed_aluminium.set_roughness(0.1, 0.6);
+    let material_brushed = Material::Conductor(brushed_aluminium);
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_right)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-0.4, -0.3, -0.2), 0.2, material_brushed)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn glass_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_center = Material::RoughDielectric(RoughDielectric::new(1.5, 0.0, Point3D::new(1.5, 0.3, 1.2)));
+    let material_left = Material::RoughDielectric(RoughDielectric::new(1.5, 0.3, Point3D::new(0.0, 0.0, 0.0)));
+    let material_right = Material::RoughDielectric(RoughDielectric::new(1.5, 0.15, Point3D::new(1.2, 0.6, 0.1)));
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_right)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn principled_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Principled(Principled { roughness: 0.8, ..Principled::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))) });
+    let material_center = Material::Principled(Principled { clearcoat: 1.0, roughness: 0.6, ..Principled::new(Texture::SolidColor(Point3D::new(0.1, 0.2, 0.5))) });
+    let material_left = Material::Principled(Principled { transmission: 1.0, roughness: 0.1, ..Principled::new(Texture::SolidColor(Point3D::new(0.9, 0.95, 1.0))) });
+    let material_right = Material::Principled(Principled { metallic: 1.0, roughness: 0.3, ..Principled::new(Texture::SolidColor(Point3D::new(0.8, 0.6, 0.2))) });
+    let material_cloth = Material::Principled(Principled { sheen: 1.0, subsurface: 0.5, roughness: 1.0, ..Principled::new(Texture::SolidColor(Point3D::new(0.6, 0.1, 0.1))) });
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_right)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-0.3, -0.35, -0.2), 0.15, material_cloth)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn coated_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    // Car paint: rough metal under a smooth clear coat.
+    let paint = Material::Conductor(Conductor::copper(0.5));
+    let material_center = Material::Coated(Coated::new(paint, 1.5, 0.0, 0.05, Point3D::new(0.0, 0.0, 0.0)));
+    // Varnish: an amber, slightly rough coat over a checkered base.
+    let board = Material::Lambertian(Lambertian::new(Texture::Checker(Checker::new(10.0, Point3D::new(0.6, 0.4, 0.25), Point3D::new(0.8, 0.6, 0.4)))));
+    let material_left = Material::Coated(Coated::new(board, 1.5, 0.1, 0.1, Point3D::new(0.5, 1.5, 4.0)));
+    let material_right = Material::Coated(Coated::new(Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.1, 0.3, 0.1)))), 1.5, 0.3, 0.05, Point3D::new(0.0, 0.0, 0.0)));
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_right)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn dispersion_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    // Rendered with --spectral, the caustics under the balls and their rims split into colors.
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_center = Material::Dielectric(Dielectric::diamond());
+    let material_left = Material::Dielectric(Dielectric::bk7());
+    // A dense flint glass, dispersing far more than crown glass.
+    let material_right = Material::Dielectric(Dielectric::with_dispersion(Dispersion::Cauchy { a: 1.67, b: 0.0174 }));
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(600.0, 600.0, 600.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_center)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_left)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_right)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.8, 1.0, -2.6), 0.05, material_lamp)));
+
+    world
+}
+
+fn iridescence_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    // A soap bubble: a film of soapy water in air.
+    let mut bubble = Dielectric::new(1.0);
+    bubble.set_film(Some(ThinFilm::new(380.0, 1.33)));
+    // Heat-tinted metal, under a layer of oxide.
+    let mut tinted = Conductor::aluminium(0.1);
+    tinted.set_film(Some(ThinFilm::new(250.0, 1.65)));
+    // Glass with an oily smear.
+    let mut smeared = Dielectric::new(1.5);
+    smeared.set_film(Some(ThinFilm::new(600.0, 1.45)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, Material::Conductor(tinted))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, Material::Dielectric(bubble))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, Material::Dielectric(smeared))));
+
+    world
+}
+
+fn subsurface_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    // Red light travels furthest through skin, and through wax.
+    let skin = Subsurface::new(Point3D::new(0.85, 0.55, 0.45), Point3D::new(0.12, 0.05, 0.03), 1.4, 0.4);
+    let marble = Subsurface::new(Point3D::new(0.9, 0.9, 0.88), Point3D::new(0.05, 0.05, 0.05), 1.5, 0.1);
+    let wax = Subsurface::new(Point3D::new(0.95, 0.8, 0.4), Point3D::new(0.3, 0.2, 0.1), 1.45, 0.2);
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground.clone())));
+    world.list.push(Box::new(Translucent::new(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, material_ground.clone())), skin)));
+    world.list.push(Box::new(Translucent::new(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, material_ground.clone())), marble)));
+    world.list.push(Box::new(Translucent::new(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, material_ground)), wax)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn bump_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    // Maps made up here; real ones would come from ImageMap::from_png.
+    let size = 256;
+    let map = |f: &dyn Fn(f64, f64) -> Point3D| {
+        let values = (0..size * size).map(|k| f((k % size) as f64 / size as f64, 1.0 - (k / size) as f64 / size as f64)).collect();
+        ImageMap::new(values, size, size)
+    };
+    // Tiles raised above the grout between them, eight to a turn of u.
+    let tiles = map(&|u, v| {
+        let grout = |x: f64| ((x * 8.0).fract() - 0.5).abs() * 2.0;
+        Point3D::new(1.0, 1.0, 1.0) * ((1.0 - grout(u).max(grout(v))) * 20.0).min(1.0)
+    });
+    // Hammered metal: dents stored as tangent-space normals.
+    let dents = map(&|u, v| {
+        let (x, y) = ((u * 24.0).fract() - 0.5, (v * 12.0).fract() - 0.5);
+        let slope = Point3D::new(-x, -y, 0.0) * 1.2;
+        (Point3D::new(slope.x(), slope.y(), 1.0).unit_vector() + Point3D::new(1.0, 1.0, 1.0)) / 2.0
+    });
+    // Ripples spreading from a point.
+    let ripples = map(&|u, v| {
+        let r = ((u - 0.5).powi(2) + (v - 0.5).powi(2)).sqrt();
+        Point3D::new(1.0, 1.0, 1.0) * (0.5 + 0.5 * (r * 60.0).sin())
+    });
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_tiles = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.7, 0.3, 0.2)) });
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::new(Point3D::new(0.0, -0.5, 0.0), Point3D::new(0.0, 1.0, 0.0), 4.0, Material::Bumped(Bumped::bump_map(material_ground, ripples, 0.05)))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, Material::Bumped(Bumped::normal_map(Material::Conductor(Conductor::copper(0.15)), dents)))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.0, 0.0, -1.0), 0.5, Material::Bumped(Bumped::bump_map(material_tiles, tiles.clone(), 0.01)))));
+    let plastic = Material::Principled(Principled { roughness: 0.3, ..Principled::new(Texture::SolidColor(Point3D::new(0.2, 0.4, 0.8))) });
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, Material::Bumped(Bumped::bump_map(plastic, tiles, 0.01)))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.3, 1.2, -0.4), 0.1, material_lamp)));
+
+    world
+}
+
+fn cutout_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let size = 256;
+    let mask = |alpha: &dyn Fn(f64, f64) -> f64| {
+        let values = (0..size * size).map(|k| Point3D::new(1.0, 1.0, 1.0) * alpha((k % size) as f64 / size as f64, 1.0 - (k / size) as f64 / size as f64)).collect();
+        ImageMap::new(values, size, size)
+    };
+    // A lantern pierced with round holes, twelve around and six from pole to pole.
+    let holes = mask(&|u, v| {
+        let (x, y) = ((u * 12.0).fract() - 0.5, (v * 6.0).fract() - 0.5);
+        if x * x + y * y < 0.09 { 0.0 } else { 1.0 }
+    });
+    // Wire mesh with diamond openings, one to a tile of the plane.
+    let lattice = mask(&|u, v| {
+        let (x, y) = ((u + v).fract() - 0.5, (u - v + 1.0).fract() - 0.5);
+        if x.abs() < 0.42 && y.abs() < 0.42 { 0.0 } else { 1.0 }
+    });
+    // Half there: a glassy ghost made of a solid color.
+    let ghost = ImageMap::new(vec![Point3D::new(0.5, 0.5, 0.5)], 1, 1);
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_lantern = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.8, 0.6, 0.3)) });
+    let material_fence = Material::Conductor(Conductor::aluminium(0.3));
+    let material_ghost = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.2, 0.4, 0.8)) });
+    let material_lamp = Material::DiffuseLight(DiffuseLight::new(Point3D::new(40.0, 36.0, 30.0)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, Material::Masked(Masked::new(material_lantern, holes)))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.1, material_lamp.clone())));
+    world.list.push(Box::new(Plane::new(Point3D::new(0.0, 0.0, -2.2), Point3D::new(0.0, 0.0, 1.0), 0.15, Material::Masked(Masked::new(material_fence, lattice)))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(1.0, 0.0, -1.0), 0.5, Material::Masked(Masked::new(material_ghost, ghost)))));
+    world.list.push(Box::new(Sphere::new(Point3D::new(-1.5, 2.5, -1.0), 0.15, material_lamp)));
+
+    world
+}
+
+fn blackbody_scene() -> HittableList {
+    let mut world = HittableList::new(Vec::new());
+
+    let material_ground = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) });
+    let material_white = Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(Point3D::new(0.8, 0.8, 0.8)) });
+    // Candle flame, tungsten bulb and overcast daylight, all equally bright.
+    let black_body = |temperature: f64| {
+        Spectrum::blackbody(temperature).and_then(|spectrum| spectrum.normalized()).map(|spectrum| DiffuseLight::from_spectrum(spectrum.scaled(40.0)))
+    };
+    let candle = lamp_or_dark("candle", black_body(1900.0));
+    let tungsten = lamp_or_dark("tungsten bulb", black_body(2700.0));
+    let daylight = lamp_or_dark("daylight lamp", black_body(6500.0));
+    // A white LED: a blue diode under a yellow phosphor, as a measured spectrum would be given,
+    // and as bright as the others by its luminous flux.
+    let led_spectrum = Spectrum::tabulated(vec![
+        (400.0, 0.0), (430.0, 0.3), (450.0, 1.0), (465.0, 0.4), (480.0, 0.15), (500.0, 0.25), (550.0, 0.6),
+        (600.0, 0.55), (650.0, 0.3), (700.0, 0.1), (750.0, 0.0),
+    ]);
+    let led = lamp_or_dark("LED", led_spectrum.and_then(|spectrum| DiffuseLight::from_lumens(spectrum, 182_700.0, 4.0 * PI * 0.04 * 0.04)));
+
+    world.list.push(Box::new(Plane::ground(-0.5, material_ground)));
+    // In a row across the view, each lit from above and in front.
+    for (t, lamp) in [(-0.6, candle), (-0.2, tungsten), (0.2, daylight), (0.6, led)] {
+        world.list.push(Box::new(Sphere::new(Point3D::new(t, -0.32, t - 1.0), 0.18, material_white.clone())));
+        world.list.push(Box::new(Sphere::new(Point3D::new(t - 0.15, 0.1, t - 0.85), 0.04, lamp)));
+    }
+
+    world
+}
+
 fn random_scene() -> HittableList {
     let mut world = HittableList::new(Vec::new());
     
-    let ground_material = Material::Lambertian(Lambertian { albedo: Point3D::new(0.5, 0.5, 0.5) } );
-    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, -1000.0, 0.0), 1000.0, ground_material)));
+    let ground_material = Material::Lambertian(Lambertian { albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) } );
+    world.list.push(Box::new(Plane::ground(0.0, ground_material)));
 
     for a in -11..11 {
         for b in -11..11 {
@@ -47,7 +506,7 @@ fn random_scene() -> HittableList {
                 if choose_mat < 0.8 {
                     // diffuse 
                     let albedo = Point3D::random() * Point3D::random();
-                    Some(Material::Lambertian(Lambertian{ albedo } ))
+                    Some(Material::Lambertian(Lambertian{ albedo: Texture::SolidColor(albedo) } ))
                 } else if choose_mat < 0.95 { 
                     // matte 
                     let albedo = Point3D::random_point_in_range(0.5, 1.0);
@@ -55,7 +514,7 @@ fn random_scene() -> HittableList {
                     Some(Material::Metal(Metal { albedo, fuzz } ))
                 } else {
                     // glass 
-                    Some(Material::Dielectric(Dielectric{ refraction_index: 1.5 } ))
+                    Some(Material::Dielectric(Dielectric::new(1.5) ))
                 }
             } else {
                 None
@@ -67,10 +526,10 @@ fn random_scene() -> HittableList {
         }
     }
 
-    let material_one = Material::Dielectric(Dielectric { refraction_index: 1.5 } );
+    let material_one = Material::Dielectric(Dielectric::new(1.5));
     world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 1.0, 0.0), 1.0, material_one)));
 
-    let material_two = Material::Lambertian(Lambertian { albedo: Point3D::new(0.4, 0.2, 0.1) } );
+    let material_two = Material::Lambertian(Lambertian { albedo: Texture::SolidColor(Point3D::new(0.4, 0.2, 0.1)) } );
     world.list.push(Box::new(Sphere::new(Point3D::new(-4.0, 1.0, 0.0), 1.0, material_two)));
 
     let material_three = Material::Metal(Metal { albedo: Point3D::new(0.7, 0.6, 0.5), fuzz: 0.0 } );
@@ -82,10 +541,10 @@ fn random_scene() -> HittableList {
 fn movie_scene() -> HittableList {
     let mut world = HittableList::new(Vec::new());
     
-    let ground_material = Material::Lambertian(Lambertian { albedo: Point3D::new(0.5, 0.5, 0.5) } );
-    world.list.push(Box::new(Sphere::new(Point3D::new(0.0, -100.5, -1.0), 100.0, ground_material)));
+    let ground_material = Material::Lambertian(Lambertian { albedo: Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5)) } );
+    world.list.push(Box::new(Plane::ground(-0.5, ground_material)));
 
-    let red_ball = Material::Lambertian(Lambertian { albedo: Point3D::new(1.0, 0.0, 0.0) } );
+    let red_ball = Material::Lambertian(Lambertian { albedo: Texture::SolidColor(Point3D::new(1.0, 0.0, 0.0)) } );
     world.list.push(Box::new(Sphere::new(Point3D::new(0.0, 0.0, -1.2), 0.5, red_ball)));
 
     world
@@ -96,8 +555,9 @@ fn movie_camera(focus_dist: f64, defocus_angle: f64) -> Camera {
     let image_width: f64 = 640.0;
     let samples_per_pixel: f64 = 100.0;
     let max_depth: usize = 20;
+    let min_depth: usize = 3;
    
-    let stats = CameraStats::new(aspect_ratio, image_width, samples_per_pixel, max_depth);
+    let stats = CameraStats::new(aspect_ratio, image_width, samples_per_pixel, max_depth, min_depth);
 
     let vfov: f64 = 40.0;
     let lookfrom = Point3D::new(-0.0, 0.0, 1.0);
@@ -116,8 +576,9 @@ fn camera_three_ball() -> Camera {
     let image_width: f64 = 400.0;
     let samples_per_pixel: f64 = 100.0;
     let max_depth: usize = 50;
+    let min_depth: usize = 3;
    
-    let stats = CameraStats::new(aspect_ratio, image_width, samples_per_pixel, max_depth);
+    let stats = CameraStats::new(aspect_ratio, image_width, samples_per_pixel, max_depth, min_depth);
 
     let vfov: f64 = 20.0;
     let lookfrom = Point3D::new(-2.0, 2.0, 1.0);
@@ -134,13 +595,20 @@ fn camera_three_ball() -> Camera {
     Camera::new(stats, view, focus)
 }
 
+fn camera_lights() -> Camera {
+    let mut camera = camera_three_ball();
+    camera.set_background(Background::Solid(Point3D::new(0.02, 0.02, 0.03)));
+    camera
+}
+
 fn camera_random() -> Camera {
     let aspect_ratio: f64 = 16.0 / 9.0;
     let image_width: f64 = 1200.0;
     let samples_per_pixel: f64 = 100.0;
     let max_depth: usize = 50;
+    let min_depth: usize = 3;
 
-    let stats = CameraStats::new(aspect_ratio, image_width, samples_per_pixel, max_depth);
+    let stats = CameraStats::new(aspect_ratio, image_width, samples_per_pixel, max_depth, min_depth);
 
     let vfov: f64 = 20.0;
     let lookfrom = Point3D::new(13.0, 2.0, 3.0);
@@ -157,9 +625,104 @@ fn camera_random() -> Camera {
     Camera::new(stats, view, focus)
 }
 
+// Scenes that can be picked by name on the command line, each with the camera it is framed for.
+// terrain:<path> reads the terrain from a PNG heightmap.
+fn scene_from_name(name: &str) -> Option<(HittableList, Camera)> {
+    if let Some(path) = name.strip_prefix("terrain:") {
+        return Some((terrain_scene(Some(path)), camera_three_ball()));
+    }
+    let scene = match name {
+        "random" => (random_scene(), camera_random()),
+        "three-balls" => (three_balls(), camera_three_ball()),
+        "movie" => (movie_scene(), movie_camera(2.2, 0.0)),
+        "csg" => (csg_scene(), camera_three_ball()),
+        "sdf" => (sdf_scene(), camera_three_ball()),
+        "quadric" => (quadric_scene(), camera_three_ball()),
+        "terrain" => (terrain_scene(None), camera_three_ball()),
+        "lights" => (lights_scene(), camera_lights()),
+        "metals" => (metals_scene(), camera_lights()),
+        "glass" => (glass_scene(), camera_lights()),
+        "principled" => (principled_scene(), camera_lights()),
+        "coated" => (coated_scene(), camera_lights()),
+        "dispersion" => (dispersion_scene(), camera_lights()),
+        "iridescence" => (iridescence_scene(), camera_three_ball()),
+        "subsurface" => (subsurface_scene(), camera_lights()),
+        "bump" => (bump_scene(), camera_lights()),
+        "cutout" => (cutout_scene(), camera_lights()),
+        "blackbody" => (blackbody_scene(), camera_lights()),
+        _ => return None,
+    };
+    Some(scene)
+}
+
 fn main() {
-    let world = random_scene(); 
-    let camera = camera_random();
+    // The scene comes first, as every other option is set on its camera.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let scene_name = args.iter().position(|arg| arg == "--scene").and_then(|i| args.get(i + 1)).map_or("random", |name| name.as_str());
+    let (world, mut camera) = scene_from_name(scene_name).unwrap_or_else(|| {
+        eprintln!("Unknown scene '{}', rendering the random one", scene_name);
+        (random_scene(), camera_random())
+    });
+
+    // Usage: ray-tracing [integrator] [--scene name] [--aovs depth,normal,...|all]
+    //                    [--denoise] [--denoise-sigmas luminance,normal,depth] [--spectral]
+    //                    [--tonemap clamp|reinhard|extended-reinhard[:white]|hable|aces|agx] [--exposure ev]
+    // The integrator defaults to the path tracer; `normals`, for example, debugs scene geometry.
+    // The scene defaults to `random`; see scene_from_name for the others.
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => {
+                args.next();
+            }
+            "--aovs" => {
+                let names = args.next().unwrap_or_default();
+                let aovs = if names == "all" {
+                    Aov::all()
+                } else {
+                    names.split(',').filter_map(|name| {
+                        let aov = Aov::from_name(name);
+                        if aov.is_none() {
+                            eprintln!("Unknown AOV '{}', skipping it", name);
+                        }
+                        aov
+                    }).collect()
+                };
+                camera.set_aovs(aovs);
+            }
+            "--denoise" => camera.set_denoiser(Some(Denoiser::new(5))),
+            // Denoises with other edge-stopping tolerances than the defaults.
+            "--denoise-sigmas" => {
+                let sigmas: Option<Vec<f64>> = args.next().unwrap_or_default().split(',').map(|sigma| sigma.parse().ok()).collect();
+                match sigmas.as_deref() {
+                    Some(&[luminance, normal, depth]) => {
+                        let mut denoiser = Denoiser::new(5);
+                        denoiser.set_sigma_luminance(luminance);
+                        denoiser.set_sigma_normal(normal);
+                        denoiser.set_sigma_depth(depth);
+                        camera.set_denoiser(Some(denoiser));
+                    }
+                    _ => eprintln!("--denoise-sigmas needs three numbers, for luminance, normal and depth"),
+                }
+            }
+            "--spectral" => camera.set_spectral(true),
+            "--tonemap" => {
+                let name = args.next().unwrap_or_default();
+                match ToneMap::from_name(&name) {
+                    Some(tone_map) => camera.set_tone_map(tone_map),
+                    None => eprintln!("Unknown tone map '{}', clamping instead", name),
+                }
+            }
+            "--exposure" => match args.next().and_then(|ev| ev.parse().ok()) {
+                Some(exposure) => camera.set_exposure(exposure),
+                None => eprintln!("--exposure needs a number of stops"),
+            },
+            name => match integrator_from_name(name) {
+                Some(integrator) => camera.set_integrator(integrator),
+                None => eprintln!("Unknown integrator '{}', using the path tracer", name),
+            },
+        }
+    }
 
     let _ = camera.render(&world, "output/multithreading.ppm");
 }
@@ -0,0 +1,50 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::random::*;
+use std::sync::Arc;
+
+// Leaves, fences and lace modelled as simple surfaces with holes cut into them by a map of
+// opacity. The first channel of the map is the alpha at each u, v: the chance that a ray meeting
+// the surface there hits it instead of passing through. Shapes ask their material before
+// reporting a hit, so holes let camera rays, shadow rays and light paths through alike, and the
+// shape goes on to its next intersection along the ray. Wrappers such as Bumped and Coated pass
+// the question on to the material they wrap, so a mask works inside them as well as around them.
+//
+// Fractional alpha is decided by a hash of the ray and the point rather than a random number, so
+// a ray tested against the same surface again, as accelerating structures may do, gets the same
+// answer.
+#[derive(Debug, Clone)]
+pub struct Masked {
+    pub base: Arc<Material>,
+    pub alpha: Arc<ImageMap>,
+}
+
+impl Masked {
+    pub fn new(base: Material, alpha: ImageMap) -> Masked {
+        Masked { base: Arc::new(base), alpha: Arc::new(alpha) }
+    }
+
+    pub fn cuts_out(&self, r: &Ray, u: f64, v: f64) -> bool {
+        let alpha = self.alpha.value(u, v).x();
+        if alpha >= 1.0 {
+            return false;
+        }
+        let (o, d) = (r.origin(), r.direction());
+        alpha <= 0.0 || hash_f64(&[o.x(), o.y(), o.z(), d.x(), d.y(), d.z(), u, v]) >= alpha
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        Scatterable::scatter(self.base.as_ref(), r_in, rec)
+    }
+
+    pub fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        Scatterable::eval(self.base.as_ref(), rec, wi, wo)
+    }
+
+    pub fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        Scatterable::pdf(self.base.as_ref(), rec, wi, wo)
+    }
+}
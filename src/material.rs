@@ -2,48 +2,208 @@ use crate::point3d::*;
 use crate::ray::*;
 use crate::hittable::*;
 use crate::random::*;
+use crate::texture::*;
+use crate::onb::*;
+use crate::microfacet::*;
+use crate::principled::*;
+use crate::layered::*;
+use crate::spectrum::*;
+use crate::thinfilm::*;
+use crate::subsurface::*;
+use crate::bump::*;
+use crate::mask::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
+    Conductor(Conductor),
     Dielectric(Dielectric),
+    RoughDielectric(RoughDielectric),
+    Principled(Principled),
+    Coated(Coated),
+    Subsurface(Subsurface), // Boundary of a Translucent object
+    Medium(Medium), // Inside of a Translucent object, where light scattered
+    Bumped(Bumped), // Another material shaded with a normal or bump map
+    Masked(Masked), // Another material with holes cut into it
+    DiffuseLight(DiffuseLight),
 }
 
+// A direction picked by a material to continue a path in. For non-specular samples f is the
+// BSDF for that direction and pdf the solid angle density it was picked with. Specular samples
+// come from a delta distribution that no other strategy can reproduce: f then already includes
+// the cosine term and pdf is the probability of the chosen lobe.
+#[derive(Debug, Clone, Copy)]
+pub struct ScatterSample {
+    pub direction: Point3D,
+    pub f: Point3D,
+    pub pdf: f64,
+    pub specular: bool,
+    pub walk: Option<Walk>, // For the ray continuing a walk through a translucent object, see hit_on_walk
+}
+
+// Directions passed to eval and pdf are unit vectors pointing away from the surface: wi towards
+// the light, wo back along the incoming ray.
 pub trait Scatterable {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (Option<Ray>, Option<Point3D>);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample>;
+    fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D;
+    fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64;
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3D;
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct Lambertian {
-    pub albedo: Point3D
+impl ScatterSample {
+    pub fn new(direction: Point3D, f: Point3D, pdf: f64, specular: bool) -> ScatterSample {
+        ScatterSample { direction, f, pdf, specular, walk: None }
+    }
+
+    // Factor by which the sample scales the light carried along its direction.
+    pub fn weight(&self, normal: &Point3D) -> Point3D {
+        if self.specular {
+            self.f / self.pdf
+        } else {
+            self.f * (normal.dot(&self.direction.unit_vector()).abs() / self.pdf)
+        }
+    }
 }
 
 impl Scatterable for Material {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (Option<Ray>, Option<Point3D>) {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
         match self {
             Material::Lambertian(l) => l.scatter(r_in, rec),
             Material::Metal(m) => m.scatter(r_in, rec),
+            Material::Conductor(c) => c.scatter(r_in, rec),
             Material::Dielectric(d) => d.scatter(r_in, rec),
+            Material::RoughDielectric(d) => d.scatter(r_in, rec),
+            Material::Principled(p) => p.scatter(r_in, rec),
+            Material::Coated(c) => c.scatter(r_in, rec),
+            Material::Subsurface(s) => s.scatter(r_in, rec),
+            Material::Medium(m) => m.scatter(r_in, rec),
+            Material::Bumped(b) => b.scatter(r_in, rec),
+            Material::Masked(m) => m.scatter(r_in, rec),
+            Material::DiffuseLight(_) => None,
+        }
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        match self {
+            Material::Lambertian(l) => l.eval(rec, wi, wo),
+            Material::Conductor(c) => c.eval(rec, wi, wo),
+            Material::RoughDielectric(d) => d.eval(rec, wi, wo),
+            Material::Principled(p) => p.eval(rec, wi, wo),
+            Material::Coated(c) => c.eval(rec, wi, wo),
+            Material::Subsurface(s) => s.eval(rec, wi, wo),
+            Material::Bumped(b) => b.eval(rec, wi, wo),
+            Material::Masked(m) => m.eval(rec, wi, wo),
+            _ => Point3D::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        match self {
+            Material::Lambertian(l) => l.pdf(rec, wi, wo),
+            Material::Conductor(c) => c.pdf(rec, wi, wo),
+            Material::RoughDielectric(d) => d.pdf(rec, wi, wo),
+            Material::Principled(p) => p.pdf(rec, wi, wo),
+            Material::Coated(c) => c.pdf(rec, wi, wo),
+            Material::Subsurface(s) => s.pdf(rec, wi, wo),
+            Material::Bumped(b) => b.pdf(rec, wi, wo),
+            Material::Masked(m) => m.pdf(rec, wi, wo),
+            _ => 0.0,
+        }
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3D {
+        match self {
+            Material::DiffuseLight(d) => d.emitted(r_in, rec),
+            _ => Point3D::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Material {
+    pub fn is_emissive(&self) -> bool {
+        matches!(self, Material::DiffuseLight(_))
+    }
+
+    // Whether a ray meeting a surface of this material at u, v passes through a hole in it,
+    // looking through wrappers for a mask on the material they wrap.
+    pub fn is_cut_out(&self, r: &Ray, u: f64, v: f64) -> bool {
+        match self {
+            Material::Masked(m) => m.cuts_out(r, u, v) || m.base.is_cut_out(r, u, v),
+            Material::Bumped(b) => b.base.is_cut_out(r, u, v),
+            Material::Coated(c) => c.base.is_cut_out(r, u, v),
+            _ => false,
+        }
+    }
+
+    // Whether the material paints a map onto the surface by its u, v and tangents, which shapes
+    // without surface coordinates cannot take.
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            Material::Bumped(_) | Material::Masked(_) => true,
+            Material::Coated(c) => c.base.is_mapped(),
+            _ => false,
+        }
+    }
+
+    // The same material with its maps taken off, for shapes that cannot take them.
+    pub fn without_maps(&self) -> Material {
+        match self {
+            Material::Bumped(b) => b.base.without_maps(),
+            Material::Masked(m) => m.base.without_maps(),
+            Material::Coated(c) => Material::Coated(Coated { base: Arc::new(c.base.without_maps()), ..c.clone() }),
+            _ => self.clone(),
         }
     }
+
+    // Overall color of the surface at the hit, independent of lighting and viewing direction.
+    pub fn albedo(&self, rec: &HitRecord) -> Point3D {
+        match self {
+            Material::Lambertian(l) => l.albedo.value(rec.u, rec.v, &rec.p),
+            Material::Metal(m) => m.albedo,
+            Material::Conductor(c) => c.reflectance(1.0),
+            Material::Principled(p) => p.base_color.value(rec.u, rec.v, &rec.p),
+            Material::Coated(c) => c.base.albedo(rec),
+            Material::Bumped(b) => b.base.albedo(rec),
+            Material::Masked(m) => m.base.albedo(rec),
+            Material::Subsurface(Subsurface { medium, .. }) | Material::Medium(medium) => medium.albedo,
+            Material::Dielectric(_) | Material::RoughDielectric(_) => Point3D::new(1.0, 1.0, 1.0),
+            Material::DiffuseLight(d) => d.emit / d.emit.max_component().max(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lambertian {
+    pub albedo: Texture
 }
 
 impl Lambertian {
-    pub fn new(albedo: Point3D) -> Lambertian {
+    pub fn new(albedo: Texture) -> Lambertian {
         Lambertian{ albedo }
     }
 
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> (Option<Ray>, Option<Point3D>) {
-        let mut scatter_direction = rec.normal + Point3D::random_unit_vector();
-        // Catch degenerate scatter direction
-        if Point3D::near_zero(&scatter_direction) {
-            scatter_direction = rec.normal;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        let uvw = Onb::new(&rec.normal);
+        let direction = uvw.transform(&Point3D::random_cosine_direction());
+        let wo = -r_in.direction().unit_vector();
+        Some(ScatterSample::new(direction, self.eval(rec, &direction, &wo), self.pdf(rec, &direction, &wo), false))
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        // Light is only reflected, so both directions have to be on the same side.
+        if rec.normal.dot(wi) <= 0.0 || rec.normal.dot(wo) <= 0.0 {
+            return Point3D::new(0.0, 0.0, 0.0);
         }
+        self.albedo.value(rec.u, rec.v, &rec.p) / PI
+    }
 
-        let scattered = Ray::new(rec.p, scatter_direction);
-        let accentuation = self.albedo;
-        (Some(scattered), Some(accentuation))
+    fn pdf(&self, rec: &HitRecord, wi: &Point3D, _wo: &Point3D) -> f64 {
+        // Cosine-weighted hemisphere sampling.
+        let cos_theta = rec.normal.dot(wi);
+        if cos_theta <= 0.0 { 0.0 } else { cos_theta / PI }
     }
 }
 
@@ -59,29 +219,152 @@ impl Metal {
         Metal{ albedo, fuzz: f }
     }
 
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (Option<Ray>, Option<Point3D>) {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        // The fuzzed reflection has no density we can evaluate, so it counts as specular.
         let reflected = Point3D::reflect(&r_in.direction(), &rec.normal);
         let reflected_fuzz = Point3D::unit_vector(&reflected) + Point3D::random_unit_vector() * self.fuzz;
-        let scattered = Ray::new(rec.p, reflected_fuzz); 
-        let accentuation = self.albedo;
-        if scattered.direction().dot(&rec.normal) > 0.0 {
-            (Some(scattered), Some(accentuation))
-        } else { 
-            (None, None)
+        if reflected_fuzz.dot(&rec.normal) > 0.0 {
+            Some(ScatterSample::new(reflected_fuzz, from_rgb(self.albedo), 1.0, true))
+        } else {
+            None
         }
     }
 }
 
+// Physically based metal: a rough surface of mirror-like microfacets (GGX), each reflecting by
+// the Fresnel equations of a conductor with complex refractive index eta + ik per color channel.
+// Unlike Metal it conserves energy apart from the light lost between the microfacets, and its
+// highlights brighten and stretch towards grazing angles as real metals do.
+#[derive(Debug, Clone, Copy)]
+pub struct Conductor {
+    pub eta: Point3D,
+    pub k: Point3D,
+    pub distribution: TrowbridgeReitz, // Roughness along the two tangents of the surface
+    pub film: Option<ThinFilm>, // Like the oxide of heated metal
+}
+
+impl Conductor {
+    // Roughness in [0, 1] along each tangent of the surface, 0 being a perfect mirror. The first
+    // tangent runs the way u grows on shapes that provide it.
+    pub fn new(eta: Point3D, k: Point3D, roughness_u: f64, roughness_v: f64) -> Conductor {
+        Conductor { eta, k, distribution: TrowbridgeReitz::new(roughness_to_alpha(roughness_u), roughness_to_alpha(roughness_v)), film: None }
+    }
+
+    pub fn set_film(&mut self, film: Option<ThinFilm>) {
+        self.film = film;
+    }
+
+    // Roughness as in new, for brushing one of the metals below along a tangent.
+    pub fn set_roughness(&mut self, roughness_u: f64, roughness_v: f64) {
+        self.distribution = TrowbridgeReitz::new(roughness_to_alpha(roughness_u), roughness_to_alpha(roughness_v));
+    }
+
+    // Measured indices at the red, green and blue wavelengths of 650, 550 and 450 nm.
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(Point3D::new(0.143119, 0.374957, 1.44248), Point3D::new(3.98316, 2.38572, 1.60322), roughness, roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(Point3D::new(0.200438, 0.924033, 1.10221), Point3D::new(3.91295, 2.45285, 2.14219), roughness, roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::new(Point3D::new(1.65746, 0.880369, 0.521229), Point3D::new(9.22387, 6.26952, 4.837), roughness, roughness)
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::new(Point3D::new(0.155265, 0.116723, 0.138342), Point3D::new(4.82835, 3.12225, 2.14696), roughness, roughness)
+    }
+
+    // Fresnel reflectance for light arriving at cos_theta from the microfacet normal.
+    pub fn reflectance(&self, cos_theta: f64) -> Point3D {
+        if let Some(film) = self.film {
+            return film.reflectance(cos_theta, 1.0, |lambda| (spectrum_value(&self.eta, lambda), spectrum_value(&self.k, lambda)));
+        }
+        let (eta, k) = (from_rgb(self.eta), from_rgb(self.k));
+        Point3D::new(
+            fresnel_conductor(cos_theta, eta.x(), k.x()),
+            fresnel_conductor(cos_theta, eta.y(), k.y()),
+            fresnel_conductor(cos_theta, eta.z(), k.z()),
+        )
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        let uvw = rec.tangent_frame();
+        let wo = uvw.local(&-r_in.direction().unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        if self.distribution.effectively_smooth() {
+            let wi = Point3D::new(-wo.x(), -wo.y(), wo.z());
+            return Some(ScatterSample::new(uvw.transform(&wi), self.reflectance(wo.z()), 1.0, true));
+        }
+        // Reflect about a normal of the microfacets visible from wo, which may still send the
+        // light into the surface, where it is lost to the masking the model accounts for.
+        let wm = self.distribution.sample_wm(&wo);
+        let wi = reflect_local(&wo, &wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let (wi, wo) = (uvw.transform(&wi), uvw.transform(&wo));
+        Some(ScatterSample::new(wi, self.eval(rec, &wi, &wo), self.pdf(rec, &wi, &wo), false))
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        let uvw = rec.tangent_frame();
+        let (wi, wo) = (uvw.local(wi), uvw.local(wo));
+        if wi.z() <= 0.0 || wo.z() <= 0.0 || self.distribution.effectively_smooth() {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        let wm = (wi + wo).unit_vector();
+        let f = self.reflectance(wo.dot(&wm));
+        f * (self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4.0 * wi.z() * wo.z()))
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        let uvw = rec.tangent_frame();
+        let (wi, wo) = (uvw.local(wi), uvw.local(wo));
+        if wi.z() <= 0.0 || wo.z() <= 0.0 || self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        // Reflecting about wm doubles the angles, which spreads the density of wm over four
+        // times the solid angle.
+        let wm = (wi + wo).unit_vector();
+        self.distribution.d_visible(&wo, &wm) / (4.0 * wo.dot(&wm))
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Dielectric {
     // Refractive index in vacuum or air, or the ratio of the material's refractive index over
     // the refractive index of the enclosing media
     pub refraction_index: f64,
+    pub dispersion: Dispersion, // Refractive index by wavelength, used in spectral mode
+    pub film: Option<ThinFilm>, // On the outside, as a soap bubble is a film of water in air
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Dielectric {
-        Dielectric{ refraction_index }
+        Dielectric{ refraction_index, dispersion: Dispersion::None, film: None }
+    }
+
+    // A dispersive glass, with the refractive index for RGB rendering taken at the yellow
+    // helium line, 587.6 nm, the usual reference of glass catalogs.
+    pub fn with_dispersion(dispersion: Dispersion) -> Dielectric {
+        Dielectric{ refraction_index: dispersion.refraction_index(587.6).unwrap_or(1.0), dispersion, film: None }
+    }
+
+    pub fn set_film(&mut self, film: Option<ThinFilm>) {
+        self.film = film;
+    }
+
+    // Schott N-BK7, the common optical crown glass.
+    pub fn bk7() -> Dielectric {
+        Dielectric::with_dispersion(Dispersion::Sellmeier { b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653] })
+    }
+
+    pub fn diamond() -> Dielectric {
+        Dielectric::with_dispersion(Dispersion::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.175 * 0.175, 0.106 * 0.106, 0.0] })
     }
 
     fn reflectance(&self, cosine: f64, refraction_index: f64) -> f64 {
@@ -90,22 +373,248 @@ impl Dielectric {
         r0_sqr + (1.0 - r0_sqr) * (1.0 - cosine).powi(5)
     }
 
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (Option<Ray>, Option<Point3D>) { 
-        let attenuation = Point3D::new(1.0, 1.0, 1.0);
-        let ri = if rec.front_face { 1.0 / self.refraction_index } else { self.refraction_index };
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        // In spectral mode a dispersive glass bends each wavelength its own way, so the path goes
+        // on at the hero's refractive index alone.
+        let (attenuation, refraction_index) = match wavelengths().and_then(|w| self.dispersion.refraction_index(w.lambda[0])) {
+            Some(refraction_index) => (terminate_secondary(), refraction_index),
+            None => (Point3D::new(1.0, 1.0, 1.0), self.refraction_index),
+        };
+        let ri = if rec.front_face { 1.0 / refraction_index } else { refraction_index };
 
         let unit_direction = r_in.direction().unit_vector();
         let cos_theta = if -unit_direction.dot(&rec.normal) < 1.0 { -unit_direction.dot(&rec.normal) } else { 1.0 };
         let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction = if cannot_refract || self.reflectance(cos_theta, ri) > random_f64() { 
-            unit_direction.reflect(&rec.normal)
-        } else { 
-            unit_direction.refract(&rec.normal, ri)
+        if cannot_refract {
+            return Some(ScatterSample::new(unit_direction.reflect(&rec.normal), attenuation, 1.0, true));
+        }
+
+        // Reflection and refraction are each picked with the probability of their Fresnel
+        // weight, which therefore cancels out of f / pdf. A film reflects each channel by its
+        // own weight, so only what is left of it after the average remains.
+        let reflectance = match self.film {
+            Some(film) if rec.front_face => film.reflectance(cos_theta, 1.0, |lambda| {
+                (self.dispersion.refraction_index(lambda).unwrap_or(refraction_index), 0.0)
+            }),
+            Some(film) => film.reflectance(cos_theta, refraction_index, |_| (1.0, 0.0)),
+            None => Point3D::new(1.0, 1.0, 1.0) * self.reflectance(cos_theta, ri),
         };
+        let probability = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
+        if probability > random_f64() {
+            Some(ScatterSample::new(unit_direction.reflect(&rec.normal), attenuation * reflectance / probability, 1.0, true))
+        } else {
+            let transmittance = Point3D::new(1.0, 1.0, 1.0) - reflectance;
+            Some(ScatterSample::new(unit_direction.refract(&rec.normal, ri), attenuation * transmittance / (1.0 - probability), 1.0, true))
+        }
+    }
+}
+
+// Frosted or colored glass: a rough boundary of microfacets that each reflect or refract by the
+// exact Fresnel equations (Walter et al. 2007), enclosing a medium that absorbs light by the
+// Beer-Lambert law. With zero roughness it is a smooth dielectric.
+//
+// Refraction compresses or spreads radiance by the squared ratio of the refractive indices. This
+// splits that evenly between entering and leaving instead, dividing by the ratio once each way,
+// which keeps the BSDF symmetric for bidirectional methods and cancels out for closed objects.
+#[derive(Debug, Clone, Copy)]
+pub struct RoughDielectric {
+    pub refraction_index: f64, // Of the inside over the outside
+    pub distribution: TrowbridgeReitz,
+    pub absorption: Point3D, // Beer-Lambert coefficient per unit of distance inside, light falls off as exp(-absorption * distance)
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64, absorption: Point3D) -> RoughDielectric {
+        let alpha = roughness_to_alpha(roughness);
+        RoughDielectric { refraction_index, distribution: TrowbridgeReitz::new(alpha, alpha), absorption }
+    }
+
+    // Ratio of the refractive index past the surface over the one on the side of the normal,
+    // which faces where the light leaves to.
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face { self.refraction_index } else { 1.0 / self.refraction_index }
+    }
+
+    // Light surviving the way from the last surface to a hit on the inside of the boundary.
+    fn transmittance(&self, rec: &HitRecord) -> Point3D {
+        if rec.front_face {
+            return Point3D::new(1.0, 1.0, 1.0);
+        }
+        let absorption = from_rgb(self.absorption);
+        let distance = rec.distance;
+        Point3D::new((-absorption.x() * distance).exp(), (-absorption.y() * distance).exp(), (-absorption.z() * distance).exp())
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        let uvw = Onb::new(&rec.normal);
+        let wo = uvw.local(&-r_in.direction().unit_vector());
+        let eta = self.eta(rec);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        if self.distribution.effectively_smooth() {
+            // Reflection and refraction are picked by their Fresnel weights, leaving only the
+            // transmittance and the refraction's share of the radiance scaling in f / pdf.
+            let reflectance = fresnel_dielectric(wo.z(), eta);
+            let normal = Point3D::new(0.0, 0.0, 1.0);
+            let (wi, f) = match refract_local(&wo, &normal, eta) {
+                Some(wi) if random_f64() >= reflectance => (wi, (1.0 - reflectance) / eta),
+                _ => (reflect_local(&wo, &normal), reflectance),
+            };
+            let pdf = if wi.z() > 0.0 { reflectance } else { 1.0 - reflectance };
+            return Some(ScatterSample::new(uvw.transform(&wi), self.transmittance(rec) * f, pdf, true));
+        }
+
+        // Reflect or refract through a normal of the microfacets visible from wo, by its Fresnel
+        // reflectance.
+        let wm = self.distribution.sample_wm(&wo);
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let wi = match refract_local(&wo, &wm, eta) {
+            Some(wi) if random_f64() >= reflectance => wi,
+            _ => reflect_local(&wo, &wm),
+        };
+        // Directions on the wrong side of the macro surface are lost to masking.
+        let reflected = wi.dot(&wm) > 0.0;
+        if wi.z() == 0.0 || (wi.z() > 0.0) != reflected {
+            return None;
+        }
+        let (wi, wo) = (uvw.transform(&wi), uvw.transform(&wo));
+        Some(ScatterSample::new(wi, self.eval(rec, &wi, &wo), self.pdf(rec, &wi, &wo), false))
+    }
 
-        let scattered = Ray::new(rec.p, direction);
-        (Some(scattered), Some(attenuation))
-    }   
+    // The microfacet normal that turns wo into wi, if any faces both of them the right way.
+    fn half_vector(&self, wi: &Point3D, wo: &Point3D, eta: f64) -> Option<Point3D> {
+        let reflected = wi.z() > 0.0;
+        let wm = if reflected { *wi + *wo } else { *wi * eta + *wo };
+        if wi.z() == 0.0 || wm.length_squared() == 0.0 {
+            return None;
+        }
+        let wm = wm.unit_vector();
+        let wm = if wm.z() < 0.0 { -wm } else { wm };
+        if wm.dot(wi) * wi.z() < 0.0 || wm.dot(wo) * wo.z() < 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        let uvw = Onb::new(&rec.normal);
+        let (wi, wo) = (uvw.local(wi), uvw.local(wo));
+        let eta = self.eta(rec);
+        let wm = match self.half_vector(&wi, &wo, eta) {
+            Some(wm) if wo.z() > 0.0 && !self.distribution.effectively_smooth() => wm,
+            _ => return Point3D::new(0.0, 0.0, 0.0),
+        };
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(&wo, &wi);
+        let f = if wi.z() > 0.0 {
+            d * g * reflectance / (4.0 * wi.z() * wo.z())
+        } else {
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2) * wi.z() * wo.z();
+            (1.0 - reflectance) * d * g * (wi.dot(&wm) * wo.dot(&wm) / denominator).abs() / eta
+        };
+        self.transmittance(rec) * f
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        let uvw = Onb::new(&rec.normal);
+        let (wi, wo) = (uvw.local(wi), uvw.local(wo));
+        let eta = self.eta(rec);
+        let wm = match self.half_vector(&wi, &wo, eta) {
+            Some(wm) if wo.z() > 0.0 && !self.distribution.effectively_smooth() => wm,
+            _ => return 0.0,
+        };
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let d_visible = self.distribution.d_visible(&wo, &wm);
+        if wi.z() > 0.0 {
+            d_visible / (4.0 * wo.dot(&wm).abs()) * reflectance
+        } else {
+            // Density of wm carried over to the refracted direction.
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            d_visible * wi.dot(&wm).abs() / denominator * (1.0 - reflectance)
+        }
+    }
+}
+
+// A surface giving off the same light in every direction, by color or by spectrum. For lamps
+// specified by their output, the light is spread over the area of the shape it is put on, which
+// the shape does not tell its material, so it has to be passed in: 4πr² for a sphere of radius r.
+#[derive(Debug, Clone)]
+pub struct DiffuseLight {
+    pub emit: Point3D, // Radiance as linear sRGB, which spectral paths also use without a spectrum
+    pub spectrum: Option<Arc<Spectrum>>, // Radiance by wavelength, for spectral paths
+}
+
+// Lumens per watt of light at the peak of the eye's sensitivity, 555 nm.
+const LUMINOUS_EFFICACY: f64 = 683.0;
+
+impl DiffuseLight {
+    pub fn new(emit: Point3D) -> DiffuseLight {
+        DiffuseLight{ emit, spectrum: None }
+    }
+
+    pub fn from_spectrum(spectrum: Spectrum) -> DiffuseLight {
+        match spectrum {
+            Spectrum::Rgb(rgb) => DiffuseLight::new(rgb),
+            _ => DiffuseLight{ emit: spectrum.rgb(), spectrum: Some(Arc::new(spectrum)) },
+        }
+    }
+
+    // A lamp of the given color or spectrum emitting a number of watts of visible light from
+    // a surface of the given area in square meters. None without an area or visible light to
+    // spread the output over.
+    pub fn from_watts(spectrum: Spectrum, watts: f64, area: f64) -> Option<DiffuseLight> {
+        let radiance = spectrum.radiance();
+        if area <= 0.0 || radiance <= 0.0 {
+            return None;
+        }
+        Some(DiffuseLight::from_spectrum(spectrum.scaled(watts / (PI * area) / radiance)))
+    }
+
+    // A lamp of the given color or spectrum with a luminous flux of a number of lumens, from a
+    // surface of the given area in square meters. None as for from_watts.
+    pub fn from_lumens(spectrum: Spectrum, lumens: f64, area: f64) -> Option<DiffuseLight> {
+        let luminous_radiance = spectrum.luminous_radiance();
+        if area <= 0.0 || luminous_radiance <= 0.0 {
+            return None;
+        }
+        Some(DiffuseLight::from_spectrum(spectrum.scaled(lumens / (LUMINOUS_EFFICACY * PI * area) / luminous_radiance)))
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Point3D {
+        // Lights only shine out of their front face.
+        if !rec.front_face {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        match (&self.spectrum, wavelengths()) {
+            (Some(spectrum), Some(_)) => from_spectrum(|lambda| spectrum.balanced_value(lambda)),
+            _ => from_rgb(self.emit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bk7_matches_the_catalog_at_the_helium_line() {
+        let bk7 = Dielectric::bk7();
+        assert!((bk7.refraction_index - 1.5168).abs() < 1e-4, "{}", bk7.refraction_index);
+        // And the catalog's values at the hydrogen F and C lines, for its dispersion.
+        assert!((bk7.dispersion.refraction_index(486.1).unwrap() - 1.5224).abs() < 1e-4);
+        assert!((bk7.dispersion.refraction_index(656.3).unwrap() - 1.5143).abs() < 1e-4);
+    }
+
+    #[test]
+    fn lamps_need_an_area_and_visible_light() {
+        let white = Spectrum::Rgb(Point3D::new(1.0, 1.0, 1.0));
+        assert!(DiffuseLight::from_watts(white.clone(), 100.0, 0.0).is_none());
+        assert!(DiffuseLight::from_lumens(white.clone(), 800.0, -1.0).is_none());
+        assert!(DiffuseLight::from_lumens(Spectrum::Rgb(Point3D::new(0.0, 0.0, 0.0)), 800.0, 1.0).is_none());
+        assert!(DiffuseLight::from_watts(white, 100.0, 1.0).is_some());
+    }
 }
@@ -0,0 +1,144 @@
+use crate::point3d::*;
+use crate::random::*;
+use std::f64::consts::PI;
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals, with the Smith model of how the
+// microfacets mask and shadow each other. Directions are in a local frame with the macro surface
+// normal along z; alpha_x and alpha_y are the roughness along x and y, equal for an isotropic
+// surface.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> TrowbridgeReitz {
+        TrowbridgeReitz { alpha_x, alpha_y }
+    }
+
+    // Below this roughness the surface is treated as a perfect mirror, whose distribution is
+    // too narrow to evaluate.
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    // Density of microfacet normals wm per unit solid angle, scaled so that their projected
+    // areas add up to the macro surface.
+    pub fn d(&self, wm: &Point3D) -> f64 {
+        if wm.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let e = x * x + y * y + wm.z() * wm.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    // Smith's auxiliary function: the area of microfacets facing away from w, relative to the
+    // visible area of those facing it.
+    fn lambda(&self, w: &Point3D) -> f64 {
+        if w.z() == 0.0 {
+            return f64::INFINITY;
+        }
+        let x = self.alpha_x * w.x();
+        let y = self.alpha_y * w.y();
+        ((1.0 + (x * x + y * y) / (w.z() * w.z())).sqrt() - 1.0) / 2.0
+    }
+
+    // Fraction of the microfacets seen from w that are not masked by others.
+    pub fn g1(&self, w: &Point3D) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction of the microfacets that are both visible from wo and lit from wi.
+    pub fn g(&self, wo: &Point3D, wi: &Point3D) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals of microfacets visible from w.
+    pub fn d_visible(&self, w: &Point3D, wm: &Point3D) -> f64 {
+        self.g1(w) / w.z().abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    // Picks a microfacet normal visible from w, with density d_visible (Heitz 2018): the
+    // visible normals of the stretched distribution are those of a hemisphere, sampled through
+    // its projection onto the plane facing w.
+    pub fn sample_wm(&self, w: &Point3D) -> Point3D {
+        let mut wh = Point3D::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).unit_vector();
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 { Point3D::new(0.0, 0.0, 1.0).cross(&wh).unit_vector() } else { Point3D::new(1.0, 0.0, 0.0) };
+        let t2 = wh.cross(&t1);
+
+        // A uniform point on the disc, squeezed to the half visible past the hemisphere's rim.
+        let r = random_f64().sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let (px, py) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - px * px).sqrt();
+        let t = (1.0 + wh.z()) / 2.0;
+        let py = (1.0 - t) * h + t * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        let nh = t1 * px + t2 * py + wh * pz;
+        Point3D::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1e-6)).unit_vector()
+    }
+}
+
+// Perceptual roughness in [0, 1] to the alpha of the distribution; squaring spreads the visible
+// change in highlights evenly over the range.
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    roughness * roughness
+}
+
+// Reflectance of a conductor with complex refractive index eta + ik, for light arriving at
+// cos_theta from the normal, averaged over polarizations.
+pub fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+// Reflectance of the boundary of a dielectric, for light arriving at cos_theta from the normal
+// on the side where eta is the ratio of the refractive index beyond the boundary over the one
+// before it. Averaged over polarizations; 1 under total internal reflection.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_theta < 0.0 { (-cos_theta, 1.0 / eta) } else { (cos_theta, eta) };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+// Refracts w through the microfacet normal wm on its side, with eta as in fresnel_dielectric.
+// None under total internal reflection.
+pub fn refract_local(w: &Point3D, wm: &Point3D, eta: f64) -> Option<Point3D> {
+    let cos_i = w.dot(wm);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*w / eta + *wm * (cos_i / eta - cos_t))
+}
+
+// Reflect w about the microfacet normal wm.
+pub fn reflect_local(w: &Point3D, wm: &Point3D) -> Point3D {
+    *wm * (2.0 * w.dot(wm)) - *w
+}
@@ -0,0 +1,219 @@
+use crate::point3d::*;
+use crate::hittable::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::integrator::*;
+use crate::film::*;
+use crate::random::*;
+use crate::color::*;
+use crate::spectrum::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Primary sample space Metropolis light transport (Kelemen et al. 2002) over the path tracer.
+// A path is determined by the random numbers the path tracer draws (including the image
+// position), so instead of drawing fresh ones for every sample, Markov chains keep mutating a
+// vector of them and keep each mutation with a probability that makes the chains visit paths in
+// proportion to the light they carry. Chains that find a hard-to-reach light path, such as one
+// through a keyhole or a lamp shade of glass, stay near it and explore its neighbours.
+pub struct MltIntegrator {
+    bootstrap_samples: usize, // Independent paths used to estimate the image brightness and start the chains
+    chains: usize, // Number of independent Markov chains
+    mutations_per_pixel: usize, // Average number of mutations per pixel, over all chains
+    sigma: f64, // Standard deviation of the small perturbations of each random number
+    large_step_probability: f64, // Chance that a mutation replaces every random number instead
+}
+
+// A random number in the primary sample vector, with the state needed to undo its last change.
+#[derive(Clone, Copy)]
+struct PrimarySample {
+    value: f64,
+    last_modified: usize, // Iteration the value was last changed in
+    value_backup: f64,
+    modified_backup: usize,
+}
+
+// Hands out the numbers of the primary sample vector, mutating each one lazily when it is first
+// asked for in an iteration. Numbers that a path did not ask for are brought up to date only when
+// a later path does, by catching up on the large steps and small steps they missed.
+struct MltSampler {
+    rng: StdRng,
+    sigma: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    current_iteration: usize,
+    large_step: bool,
+    last_large_step_iteration: usize,
+    sample_index: usize, // Index of the next number to hand out in this iteration
+}
+
+impl MltSampler {
+    // Samplers made with the same seed hand out the same numbers, so that a chain can start from
+    // a path found while bootstrapping.
+    fn new(seed: u64, sigma: f64, large_step_probability: f64) -> MltSampler {
+        MltSampler {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            samples: Vec::new(),
+            current_iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+            sample_index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.sample_index = 0;
+    }
+
+    fn next(&mut self) -> f64 {
+        let index = self.sample_index;
+        self.sample_index += 1;
+        while index >= self.samples.len() {
+            // A number no path has asked for yet is as good as drawn at the last large step.
+            let value = self.rng.gen::<f64>();
+            let last_modified = self.last_large_step_iteration;
+            self.samples.push(PrimarySample { value, last_modified, value_backup: value, modified_backup: last_modified });
+        }
+
+        let sample = &mut self.samples[index];
+        // A large step since the last change replaced the number with a fresh one.
+        if sample.last_modified < self.last_large_step_iteration {
+            sample.value = self.rng.gen::<f64>();
+            sample.last_modified = self.last_large_step_iteration;
+        }
+        sample.value_backup = sample.value;
+        sample.modified_backup = sample.last_modified;
+        if self.large_step {
+            sample.value = self.rng.gen::<f64>();
+        } else {
+            // The small steps it missed add up to one normal perturbation of wider spread.
+            let missed_steps = (self.current_iteration - sample.last_modified) as f64;
+            let u1: f64 = self.rng.gen::<f64>();
+            let u2: f64 = self.rng.gen::<f64>();
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            sample.value += normal * self.sigma * missed_steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modified = self.current_iteration;
+        sample.value
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modified == self.current_iteration {
+                sample.value = sample.value_backup;
+                sample.last_modified = sample.modified_backup;
+            }
+        }
+        self.current_iteration -= 1;
+    }
+}
+
+impl MltIntegrator {
+    pub fn new(bootstrap_samples: usize, chains: usize, mutations_per_pixel: usize) -> MltIntegrator {
+        MltIntegrator { bootstrap_samples, chains, mutations_per_pixel, sigma: 0.01, large_step_probability: 0.3 }
+    }
+
+    pub fn set_sigma(&mut self, sigma: f64) {
+        self.sigma = sigma;
+    }
+
+    pub fn set_large_step_probability(&mut self, large_step_probability: f64) {
+        self.large_step_probability = large_step_probability;
+    }
+
+    // Runs the path tracer on the numbers of sampler. Returns the light it found and where on the
+    // image, in pixel units, the path starts.
+    fn radiance(&self, sampler: &Rc<RefCell<MltSampler>>, camera: &Camera, world: &dyn Hittable, lights: &LightList, film: &Film) -> (Point3D, (f64, f64)) {
+        let source = Rc::clone(sampler);
+        set_sample_source(Some(Box::new(move || source.borrow_mut().next())));
+        let stats = camera.stats();
+        let x = random_f64() * stats.width();
+        let y = random_f64() * stats.height();
+        let r = camera.get_ray(x.floor(), y.floor());
+        if camera.spectral() {
+            set_wavelengths(Some(Wavelengths::sample(random_f64())));
+        }
+        let color = to_rgb(PathIntegrator.li(&r, camera, world, lights, film));
+        set_wavelengths(None);
+        set_sample_source(None);
+        (color, (x, y))
+    }
+}
+
+impl Integrator for MltIntegrator {
+    fn render(&self, camera: &Camera, world: &dyn Hittable) -> Vec<Point3D> {
+        let stats = camera.stats();
+        let (width, height) = (stats.width() as usize, stats.height() as usize);
+        let lights = LightList::new(world);
+        let film = Film::new(width, height);
+        let unused = Film::new(0, 0); // The path tracer splats nothing
+
+        // Bootstrap: the average brightness of independent paths normalizes the image, and the
+        // paths themselves are candidates to start the chains from, picked by brightness.
+        let weights: Vec<f64> = (0..self.bootstrap_samples).into_par_iter().map(|index| {
+            let sampler = Rc::new(RefCell::new(MltSampler::new(index as u64, self.sigma, self.large_step_probability)));
+            luminance(&self.radiance(&sampler, camera, world, &lights, &unused).0)
+        }).collect();
+        let cdf: Vec<f64> = weights.iter().scan(0.0, |sum, weight| {
+            *sum += weight;
+            Some(*sum)
+        }).collect();
+        let total = cdf.last().copied().unwrap_or(0.0);
+        if total <= 0.0 {
+            return vec![Point3D::new(0.0, 0.0, 0.0); width * height];
+        }
+        let brightness = total / self.bootstrap_samples as f64;
+
+        let total_mutations = self.mutations_per_pixel * width * height;
+        (0..self.chains).into_par_iter().for_each(|chain| {
+            let mutations = total_mutations / self.chains + usize::from(chain < total_mutations % self.chains);
+            let mut rng = StdRng::seed_from_u64((self.bootstrap_samples + chain) as u64);
+            let target = rng.gen::<f64>() * total;
+            let start = cdf.partition_point(|sum| *sum <= target).min(self.bootstrap_samples - 1);
+
+            let sampler = Rc::new(RefCell::new(MltSampler::new(start as u64, self.sigma, self.large_step_probability)));
+            let (mut current, mut current_position) = self.radiance(&sampler, camera, world, &lights, &unused);
+            for _ in 0..mutations {
+                sampler.borrow_mut().start_iteration();
+                let (proposed, proposed_position) = self.radiance(&sampler, camera, world, &lights, &unused);
+                let (proposed_luminance, current_luminance) = (luminance(&proposed), luminance(&current));
+                // A chain stuck on a path without light, as spectral estimates that go negative
+                // can leave it on, takes any move away from it.
+                let accept = if current_luminance > 0.0 { (proposed_luminance / current_luminance).clamp(0.0, 1.0) } else { 1.0 };
+
+                // Splat both paths, weighted by their chance of being the next state, instead of
+                // only the one chosen. Dark paths carry nothing to splat.
+                if accept > 0.0 && proposed_luminance > 0.0 {
+                    film.add_splat(proposed_position.0, proposed_position.1, proposed * (accept / proposed_luminance));
+                }
+                if accept < 1.0 {
+                    film.add_splat(current_position.0, current_position.1, current * ((1.0 - accept) / current_luminance));
+                }
+
+                if rng.gen::<f64>() < accept {
+                    current = proposed;
+                    current_position = proposed_position;
+                    sampler.borrow_mut().accept();
+                } else {
+                    sampler.borrow_mut().reject();
+                }
+            }
+        });
+
+        film.splats().iter().map(|splat| *splat * (brightness / self.mutations_per_pixel as f64)).collect()
+    }
+}
@@ -0,0 +1,70 @@
+use crate::point3d::*;
+
+// Orthonormal basis with w along a given direction, for building directions in a local frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    u: Point3D,
+    v: Point3D,
+    w: Point3D,
+}
+
+impl Onb {
+    pub fn new(n: &Point3D) -> Onb {
+        let w = n.unit_vector();
+        // Any vector not parallel to w completes the basis.
+        let a = if w.x().abs() > 0.9 { Point3D::new(0.0, 1.0, 0.0) } else { Point3D::new(1.0, 0.0, 0.0) };
+        let v = w.cross(&a).unit_vector();
+        let u = v.cross(&w); // Right-handed, u x v = w, like with_tangent
+        Onb { u, v, w }
+    }
+
+    // A basis with u along the part of tangent perpendicular to n, or any basis if there is none.
+    pub fn with_tangent(n: &Point3D, tangent: &Point3D) -> Onb {
+        let w = n.unit_vector();
+        let along = *tangent - w * w.dot(tangent);
+        let usable = along.length_squared() > 1e-12 * tangent.length_squared(); // False for a zero or NaN tangent
+        if !usable {
+            return Onb::new(n);
+        }
+        let u = along.unit_vector();
+        Onb { u, v: w.cross(&u), w }
+    }
+
+    pub fn u(&self) -> Point3D {
+        self.u
+    }
+
+    pub fn v(&self) -> Point3D {
+        self.v
+    }
+
+    pub fn w(&self) -> Point3D {
+        self.w
+    }
+
+    // From basis coordinates to world coordinates.
+    pub fn transform(&self, a: &Point3D) -> Point3D {
+        self.u * a.x() + self.v * a.y() + self.w * a.z()
+    }
+
+    // From world coordinates to basis coordinates.
+    pub fn local(&self, a: &Point3D) -> Point3D {
+        Point3D::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bases_are_right_handed() {
+        let tangent = Point3D::new(0.3, 0.5, -0.2);
+        for n in [Point3D::new(0.0, 1.0, 0.0), Point3D::new(1.0, 0.0, 0.0), Point3D::new(-0.2, -0.7, 0.4)] {
+            for uvw in [Onb::new(&n), Onb::with_tangent(&n, &tangent)] {
+                assert!((uvw.u().cross(&uvw.v()) - uvw.w()).length() < 1e-12, "{:?}", uvw);
+                assert!(uvw.u().dot(&uvw.v()).abs() < 1e-12 && uvw.u().dot(&uvw.w()).abs() < 1e-12, "{:?}", uvw);
+            }
+        }
+    }
+}
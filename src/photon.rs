@@ -0,0 +1,225 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::integrator::*;
+use crate::kdtree::*;
+use crate::onb::*;
+use crate::random::*;
+use crate::subsurface::*;
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+// Photon mapping (Jensen): photons are traced from the lights and stored wherever they land on a
+// non-specular surface, then camera rays follow specular bounces to the first non-specular
+// surface and estimate the light there from the density of nearby photons. Caustics, which the
+// path tracer can only find by chance, come out smooth, at the cost of some blur (bias) that
+// shrinks with the gather radius.
+//
+// Only emissive objects emit photons; light from the background is seen only by camera rays
+// that escape the scene directly or through specular bounces.
+pub struct PhotonMapIntegrator {
+    photons: usize, // Number of photons emitted from the lights
+    radius: f64, // Distance from a hit within which photons are gathered
+}
+
+// Stochastic progressive photon mapping (Hachisuka and Jensen 2009). Each of the camera's
+// samples per pixel becomes an iteration that traces one camera ray per pixel and a fresh batch
+// of photons. Every pixel gathers the new photons around its visible point and then shrinks its
+// gather radius, so the blur of plain photon mapping vanishes as iterations go on.
+pub struct SppmIntegrator {
+    photons_per_iteration: usize,
+    initial_radius: f64,
+    alpha: f64, // Fraction of newly gathered photons kept each iteration, in (0, 1)
+}
+
+// Where a camera ray first reaches a non-specular surface, and what it carries back from there.
+#[derive(Clone)]
+struct VisiblePoint {
+    rec: HitRecord,
+    wo: Point3D, // Unit vector back along the camera ray
+    beta: Point3D, // Fraction of the light leaving the point that reaches the camera
+}
+
+// Progress of one pixel through the iterations of SPPM.
+#[derive(Clone, Copy)]
+struct PixelState {
+    radius: f64,
+    photon_count: f64, // Number of photons accounted for in flux, after shrinking
+    flux: Point3D, // Gathered photon power reflected towards the camera, for the current radius
+    direct: Point3D, // Sum over iterations of the light found on the way to the visible point
+}
+
+// Traces count photons from the lights. Their powers are divided by count so that the photons of
+// one call together carry all the light emitted by the scene.
+fn trace_photons(camera: &Camera, world: &dyn Hittable, lights: &LightList, count: usize) -> Vec<Photon> {
+    let stats = camera.stats();
+    (0..count).into_par_iter().flat_map_iter(|_| {
+        let mut photons = Vec::new();
+        let (rec, pdf_position) = match lights.sample_surface() {
+            Some(sample) => sample,
+            None => return photons,
+        };
+        // Leave the light in a cosine-weighted direction, which cancels against the emission.
+        let direction = Onb::new(&rec.normal).transform(&Point3D::random_cosine_direction());
+        let emitted = Scatterable::emitted(&rec.material, &Ray::new(rec.p + direction, -direction), &rec);
+        let mut power = emitted * (PI / (pdf_position * count as f64));
+        let mut ray = Ray::new(rec.p, direction);
+        let mut walk: Option<Walk> = None; // Through a translucent object, when the path is on one
+
+        for depth in 0..stats.max_depth() {
+            let rec = match hit_on_walk(world, &ray, walk.as_ref(), 0.001, f64::MAX) {
+                Some(rec) => rec,
+                None => break,
+            };
+            let sample = match Scatterable::scatter(&rec.material, &ray, &rec) {
+                Some(sample) => sample,
+                None => break,
+            };
+            if !sample.specular {
+                photons.push(Photon { p: rec.p, wi: -ray.direction().unit_vector(), power });
+            }
+            let weight = sample.weight(&rec.normal);
+            power = power * weight;
+            walk = sample.walk;
+            ray = Ray::new(rec.p, sample.direction);
+
+            // Russian roulette on the fraction of power the bounce kept, so that the surviving
+            // photons keep similar powers.
+            if depth + 1 >= stats.min_depth() {
+                let survival = weight.max_component().min(1.0);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                power = power / survival;
+            }
+        }
+        photons
+    }).collect()
+}
+
+// Follows r through specular bounces to the first non-specular surface. Returns the light found
+// on the way there, and the surface unless the ray escaped, was absorbed or ran out of bounces.
+fn find_visible_point(r: &Ray, camera: &Camera, world: &dyn Hittable) -> (Point3D, Option<VisiblePoint>) {
+    let mut radiance = Point3D::new(0.0, 0.0, 0.0);
+    let mut beta = Point3D::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    let mut walk: Option<Walk> = None; // Through a translucent object, when the path is on one
+    for _ in 0..camera.stats().max_depth() {
+        let rec = match hit_on_walk(world, &ray, walk.as_ref(), 0.001, f64::MAX) {
+            Some(rec) => rec,
+            None => return (radiance + beta * camera.background_color(&ray), None),
+        };
+        radiance = radiance + beta * Scatterable::emitted(&rec.material, &ray, &rec);
+        let sample = match Scatterable::scatter(&rec.material, &ray, &rec) {
+            Some(sample) => sample,
+            None => break,
+        };
+        if !sample.specular {
+            return (radiance, Some(VisiblePoint { rec, wo: -ray.direction().unit_vector(), beta }));
+        }
+        beta = beta * sample.weight(&rec.normal);
+        walk = sample.walk;
+        ray = Ray::new(rec.p, sample.direction);
+    }
+    (radiance, None)
+}
+
+impl VisiblePoint {
+    // Sum of the photon powers within radius reflected towards the camera, and how many photons
+    // were found.
+    fn gather(&self, photons: &KdTree, radius: f64) -> (Point3D, usize) {
+        let mut flux = Point3D::new(0.0, 0.0, 0.0);
+        let mut count = 0;
+        photons.for_each_within(&self.rec.p, radius, &mut |photon| {
+            flux = flux + photon.power * Scatterable::eval(&self.rec.material, &self.rec, &photon.wi, &self.wo);
+            count += 1;
+        });
+        (flux, count)
+    }
+}
+
+impl PhotonMapIntegrator {
+    pub fn new(photons: usize, radius: f64) -> PhotonMapIntegrator {
+        PhotonMapIntegrator { photons, radius }
+    }
+}
+
+impl Integrator for PhotonMapIntegrator {
+    fn render(&self, camera: &Camera, world: &dyn Hittable) -> Vec<Point3D> {
+        let stats = camera.stats();
+        let (width, height) = (stats.width() as usize, stats.height() as usize);
+        let samples_per_pixel = stats.samples_per_pixel();
+        let lights = LightList::new(world);
+        let photons = KdTree::new(trace_photons(camera, world, &lights, self.photons));
+        if photons.is_empty() {
+            eprintln!("No photons reached a surface; photon mapping needs a light in the scene");
+        }
+
+        (0..width * height).into_par_iter().map(|index| {
+            let (w, h) = (index % width, index / width);
+            let mut pixel_color = Point3D::new(0.0, 0.0, 0.0);
+            for _ in 0..samples_per_pixel {
+                let r = camera.get_ray(w as f64, h as f64);
+                let (radiance, visible) = find_visible_point(&r, camera, world);
+                pixel_color = pixel_color + radiance;
+                if let Some(visible) = visible {
+                    // Density estimate: the gathered power spread over the disc it was gathered from.
+                    let (flux, _) = visible.gather(&photons, self.radius);
+                    pixel_color = pixel_color + visible.beta * flux / (PI * self.radius * self.radius);
+                }
+            }
+            pixel_color / samples_per_pixel as f64
+        }).collect()
+    }
+}
+
+impl SppmIntegrator {
+    pub fn new(photons_per_iteration: usize, initial_radius: f64, alpha: f64) -> SppmIntegrator {
+        SppmIntegrator { photons_per_iteration, initial_radius, alpha }
+    }
+}
+
+impl Integrator for SppmIntegrator {
+    fn render(&self, camera: &Camera, world: &dyn Hittable) -> Vec<Point3D> {
+        let stats = camera.stats();
+        let (width, height) = (stats.width() as usize, stats.height() as usize);
+        let iterations = stats.samples_per_pixel();
+        let lights = LightList::new(world);
+        let start = PixelState { radius: self.initial_radius, photon_count: 0.0, flux: Point3D::new(0.0, 0.0, 0.0), direct: Point3D::new(0.0, 0.0, 0.0) };
+        let mut pixels = vec![start; width * height];
+
+        for _ in 0..iterations {
+            let photons = KdTree::new(trace_photons(camera, world, &lights, self.photons_per_iteration));
+            pixels.par_iter_mut().enumerate().for_each(|(index, pixel)| {
+                let (w, h) = (index % width, index / width);
+                let r = camera.get_ray(w as f64, h as f64);
+                let (radiance, visible) = find_visible_point(&r, camera, world);
+                pixel.direct = pixel.direct + radiance;
+                let visible = match visible {
+                    Some(visible) => visible,
+                    None => return,
+                };
+                let (flux, count) = visible.gather(&photons, pixel.radius);
+                if count == 0 {
+                    return;
+                }
+                // Keep only a fraction alpha of the new photons, and shrink the radius so that
+                // the photon density stays the same.
+                let photon_count = pixel.photon_count + self.alpha * count as f64;
+                let radius = pixel.radius * (photon_count / (pixel.photon_count + count as f64)).sqrt();
+                pixel.flux = (pixel.flux + visible.beta * flux) * ((radius * radius) / (pixel.radius * pixel.radius));
+                pixel.photon_count = photon_count;
+                pixel.radius = radius;
+            });
+        }
+
+        // Every iteration's photons carry all the emitted light, so the flux covers iterations
+        // times the light of the scene.
+        pixels.iter().map(|pixel| {
+            pixel.direct / iterations as f64 + pixel.flux / (iterations as f64 * PI * pixel.radius * pixel.radius)
+        }).collect()
+    }
+}
@@ -0,0 +1,91 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use crate::onb::*;
+
+// An infinite plane through a point. The side the normal points to is outside; as a solid, the
+// plane is the half-space behind it.
+pub struct Plane {
+    point: Point3D,
+    normal: Point3D,
+    u_axis: Point3D, // In-plane directions along which u and v grow
+    v_axis: Point3D,
+    tile_size: f64, // World distance over which u and v grow by 1, the size of one tile of a map
+    material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Point3D, normal: Point3D, tile_size: f64, material: Material) -> Plane {
+        let basis = Onb::new(&normal);
+        Plane { point, normal: basis.w(), u_axis: basis.u(), v_axis: basis.v(), tile_size, material }
+    }
+
+    // A horizontal ground plane at the given height, facing up.
+    pub fn ground(height: f64, material: Material) -> Plane {
+        Plane::new(Point3D::new(0.0, height, 0.0), Point3D::new(0.0, 1.0, 0.0), 1.0, material)
+    }
+
+    fn crossing(&self, r: &Ray) -> Option<f64> {
+        let denominator = self.normal.dot(&r.direction());
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        Some((self.point - r.origin()).dot(&self.normal) / denominator)
+    }
+
+    fn record(&self, r: &Ray, t: f64) -> HitRecord {
+        let p = r.at(t);
+        let offset = p - self.point;
+        // Left unwrapped so that textures with a pattern finer than a tile, like a checker of
+        // odd scale, carry on across tiles. Maps wrap their lookups themselves.
+        let u = offset.dot(&self.u_axis) / self.tile_size;
+        let v = offset.dot(&self.v_axis) / self.tile_size;
+        let mut rec = HitRecord::new(p, self.normal, t, true, self.material.clone(), u, v);
+        rec.set_tangents(self.u_axis * self.tile_size, self.v_axis * self.tile_size);
+        rec
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        let t = self.crossing(r)?;
+        if t <= ray_tmin || t >= ray_tmax {
+            return None;
+        }
+        let mut rec = self.record(r, t);
+        if rec.is_cut_out(r) {
+            return None;
+        }
+        rec.set_face_normal(r, self.normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::universe()
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+impl Solid for Plane {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        // The half-space reaches to infinity, so one end of the span always lies there.
+        let at_infinity = |t: f64, front_face: bool| {
+            HitRecord::new(r.at(t), self.normal, t, front_face, self.material.clone(), 0.0, 0.0)
+        };
+        match self.crossing(r) {
+            Some(t) if r.direction().dot(&self.normal) < 0.0 => {
+                vec![Span::new(self.record(r, t), at_infinity(f64::INFINITY, false))]
+            }
+            Some(t) => vec![Span::new(at_infinity(f64::NEG_INFINITY, true), self.record(r, t))],
+            None if (r.origin() - self.point).dot(&self.normal) < 0.0 => {
+                vec![Span::new(at_infinity(f64::NEG_INFINITY, true), at_infinity(f64::INFINITY, false))]
+            }
+            None => Vec::new(),
+        }
+    }
+}
@@ -25,6 +25,15 @@ impl Point3D {
         self.z
     }
 
+    // Coordinate along axis 0 (x), 1 (y) or 2 (z).
+    pub fn axis(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
     pub fn length(&self) -> f64 {
         f64::sqrt(self.length_squared())
     }
@@ -33,10 +42,8 @@ impl Point3D {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn near_zero(&self) -> bool {
-        // Return true if the vector is close to zero in all dimensions.
-        let s = 1e-8;
-        self.x() < s && self.y() < s && self.z() < s
+    pub fn max_component(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
     }
 
     pub fn random() -> Point3D {
@@ -57,11 +64,13 @@ impl Point3D {
     }
 
     pub fn random_in_unit_disk() -> Point3D {
-        Point3D::new(
-            random_in_range(-1.0, 1.0),
-            random_in_range(-1.0, 1.0),
-            0.0
-        )
+        // Rejection sampled, so that points are uniform over the disk and not its bounding square.
+        loop {
+            let p = Point3D::new(random_in_range(-1.0, 1.0), random_in_range(-1.0, 1.0), 0.0);
+            if p.length_squared() < 1.0 {
+                return p;
+            }
+        }
     }
 
     pub fn random_unit_vector() -> Point3D {
@@ -74,13 +83,15 @@ impl Point3D {
         }
     }
 
-    pub fn random_on_hemisphere(normal: &Point3D) -> Point3D {
-        let on_unit_sphere = Point3D::random_unit_vector();
-        if on_unit_sphere.dot(normal) > 0.0 { // In the same hemisphere as the normal
-            on_unit_sphere 
-        } else {
-            -on_unit_sphere
-        }
+    pub fn random_cosine_direction() -> Point3D {
+        // Direction on the +z hemisphere with density proportional to its z component.
+        let r1 = random_f64();
+        let r2 = random_f64();
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+        let z = (1.0 - r2).sqrt();
+        Point3D::new(x, y, z)
     }
 
     pub fn reflect(&self, normal: &Point3D) -> Point3D {
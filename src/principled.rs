@@ -0,0 +1,263 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::microfacet::*;
+use crate::onb::*;
+use crate::color::*;
+use crate::random::*;
+use std::f64::consts::PI;
+
+// The Disney principled BSDF (Burley 2012, 2015): one material whose parameters, all in [0, 1]
+// except ior, blend between plastics, metals, fabrics and glass the way artists expect. It sums
+// four lobes:
+// - diffuse, with Burley's grazing retro-reflection, blended towards a flattened look that
+//   fakes subsurface scattering, plus a sheen for cloth;
+// - specular reflection off GGX microfacets, tinted towards the base color by metallic;
+// - a clear coat: a second, fixed-index specular layer with a long-tailed distribution;
+// - glass transmission, a rough dielectric tinted by the base color.
+// Construct with struct update syntax from new, e.g. Principled { metallic: 1.0, ..Principled::new(texture) }.
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: f64, // Conductor (1) or dielectric (0)
+    pub roughness: f64, // Of the specular and glass lobes, and of the diffuse retro-reflection
+    pub specular: f64, // Dielectric reflectance at normal incidence; 0.5 is 4%, an index of 1.5
+    pub specular_tint: f64, // How much dielectric highlights take the hue of the base color
+    pub sheen: f64, // Extra grazing reflection, for cloth
+    pub sheen_tint: f64, // How much the sheen takes the hue of the base color
+    pub clearcoat: f64, // Strength of the clear coat
+    pub clearcoat_gloss: f64, // Smoothness of the clear coat
+    pub transmission: f64, // Fraction of the dielectric part that is glass
+    pub subsurface: f64, // Blend of the diffuse lobe towards the subsurface approximation
+    pub ior: f64, // Refractive index of the glass
+}
+
+// Probabilities of sampling each lobe, summing to one.
+struct LobeProbabilities {
+    diffuse: f64,
+    specular: f64,
+    clearcoat: f64,
+    glass: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Texture) -> Principled {
+        Principled {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            subsurface: 0.0,
+            ior: 1.5,
+        }
+    }
+
+    fn base(&self, rec: &HitRecord) -> Point3D {
+        self.base_color.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        let alpha = roughness_to_alpha(self.roughness).max(1e-3);
+        TrowbridgeReitz::new(alpha, alpha)
+    }
+
+    fn glass(&self) -> Material {
+        Material::RoughDielectric(RoughDielectric { refraction_index: self.ior, distribution: self.distribution(), absorption: Point3D::new(0.0, 0.0, 0.0) })
+    }
+
+    // Weight of each lobe in the sum, leaving out the base color and Fresnel.
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn specular_weight(&self) -> f64 {
+        1.0 - self.transmission * (1.0 - self.metallic)
+    }
+
+    fn glass_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    // Light inside an object only meets the glass; the other lobes describe its outer surface.
+    fn only_glass(&self, rec: &HitRecord) -> bool {
+        !rec.front_face && self.transmission > 0.0
+    }
+
+    // Specular color at normal incidence.
+    fn specular_color(&self, base: &Point3D) -> Point3D {
+        let tint = lerp(&Point3D::new(1.0, 1.0, 1.0), &hue(base), self.specular_tint);
+        lerp(&(tint * (0.08 * self.specular)), base, self.metallic)
+    }
+
+    // Chance of sampling each lobe, from how much light it is expected to reflect towards wo.
+    fn probabilities(&self, rec: &HitRecord, wo: &Point3D) -> LobeProbabilities {
+        if self.only_glass(rec) {
+            return LobeProbabilities { diffuse: 0.0, specular: 0.0, clearcoat: 0.0, glass: 1.0 };
+        }
+        let base = self.base(rec);
+        let diffuse = (1.0 - self.metallic) * ((1.0 - self.transmission) * luminance(&base) + self.sheen);
+        let specular = self.specular_weight() * luminance(&schlick(&self.specular_color(&base), wo.z())).max(0.02);
+        let clearcoat = 0.25 * self.clearcoat * schlick_scalar(0.04, wo.z());
+        let glass = self.glass_weight();
+        let total = diffuse + specular + clearcoat + glass;
+        if total <= 0.0 {
+            return LobeProbabilities { diffuse: 1.0, specular: 0.0, clearcoat: 0.0, glass: 0.0 };
+        }
+        LobeProbabilities { diffuse: diffuse / total, specular: specular / total, clearcoat: clearcoat / total, glass: glass / total }
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        let uvw = Onb::new(&rec.normal);
+        let wo_world = -r_in.direction().unit_vector();
+        let wo = uvw.local(&wo_world);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let probabilities = self.probabilities(rec, &wo);
+        let u = random_f64();
+        let wi = if u < probabilities.diffuse {
+            uvw.transform(&Point3D::random_cosine_direction())
+        } else if u < probabilities.diffuse + probabilities.specular {
+            uvw.transform(&reflect_local(&wo, &self.distribution().sample_wm(&wo)))
+        } else if u < probabilities.diffuse + probabilities.specular + probabilities.clearcoat {
+            uvw.transform(&reflect_local(&wo, &sample_gtr1(self.clearcoat_alpha())))
+        } else {
+            Scatterable::scatter(&self.glass(), r_in, rec)?.direction.unit_vector()
+        };
+        let pdf = self.pdf(rec, &wi, &wo_world);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterSample::new(wi, self.eval(rec, &wi, &wo_world), pdf, false))
+    }
+
+    pub fn eval(&self, rec: &HitRecord, wi_world: &Point3D, wo_world: &Point3D) -> Point3D {
+        let uvw = Onb::new(&rec.normal);
+        let (wi, wo) = (uvw.local(wi_world), uvw.local(wo_world));
+        if wo.z() <= 0.0 {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        let base = self.base(rec);
+        let mut f = Point3D::new(0.0, 0.0, 0.0);
+        if self.glass_weight() > 0.0 {
+            let glass = Scatterable::eval(&self.glass(), rec, wi_world, wo_world);
+            // Each crossing of the surface takes the square root of the tint, so that light
+            // through the whole object takes on the base color.
+            let tint = if wi.z() < 0.0 { Point3D::new(base.x().sqrt(), base.y().sqrt(), base.z().sqrt()) } else { Point3D::new(1.0, 1.0, 1.0) };
+            let weight = if self.only_glass(rec) { 1.0 } else { self.glass_weight() };
+            f = f + glass * tint * weight;
+        }
+        if self.only_glass(rec) || wi.z() <= 0.0 {
+            return f;
+        }
+
+        let wm = (wi + wo).unit_vector();
+        let cos_d = wi.dot(&wm); // Angle between the light and the microfacet normal
+        let (cos_i, cos_o) = (wi.z(), wo.z());
+
+        // Diffuse, with retro-reflection growing with roughness, or its subsurface stand-in.
+        let fi = (1.0 - cos_i).powi(5);
+        let fo = (1.0 - cos_o).powi(5);
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * fi) * (1.0 + (fd90 - 1.0) * fo);
+        let fss90 = self.roughness * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fi) * (1.0 + (fss90 - 1.0) * fo);
+        let ss = 1.25 * (fss * (1.0 / (cos_i + cos_o) - 0.5) + 0.5);
+        f = f + base * (self.diffuse_weight() * (fd + (ss - fd) * self.subsurface) / PI);
+
+        // Sheen.
+        let sheen_color = lerp(&Point3D::new(1.0, 1.0, 1.0), &hue(&base), self.sheen_tint);
+        f = f + sheen_color * ((1.0 - self.metallic) * self.sheen * (1.0 - cos_d).powi(5));
+
+        // Specular.
+        let distribution = self.distribution();
+        let specular = schlick(&self.specular_color(&base), cos_d) * (distribution.d(&wm) * distribution.g(&wo, &wi) / (4.0 * cos_i * cos_o));
+        f = f + specular * self.specular_weight();
+
+        // Clear coat, with the fixed roughness of 0.25 for its masking-shadowing.
+        if self.clearcoat > 0.0 {
+            let g = smith_ggx(cos_i, 0.25) * smith_ggx(cos_o, 0.25);
+            let clearcoat = 0.25 * self.clearcoat * gtr1(wm.z(), self.clearcoat_alpha()) * schlick_scalar(0.04, cos_d) * g;
+            f = f + Point3D::new(clearcoat, clearcoat, clearcoat);
+        }
+        f
+    }
+
+    pub fn pdf(&self, rec: &HitRecord, wi_world: &Point3D, wo_world: &Point3D) -> f64 {
+        let uvw = Onb::new(&rec.normal);
+        let (wi, wo) = (uvw.local(wi_world), uvw.local(wo_world));
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let probabilities = self.probabilities(rec, &wo);
+        let mut pdf = 0.0;
+        if probabilities.glass > 0.0 {
+            pdf += probabilities.glass * Scatterable::pdf(&self.glass(), rec, wi_world, wo_world);
+        }
+        if wi.z() <= 0.0 {
+            return pdf;
+        }
+        let wm = (wi + wo).unit_vector();
+        pdf += probabilities.diffuse * wi.z() / PI;
+        pdf += probabilities.specular * self.distribution().d_visible(&wo, &wm) / (4.0 * wo.dot(&wm));
+        pdf += probabilities.clearcoat * gtr1(wm.z(), self.clearcoat_alpha()) * wm.z() / (4.0 * wo.dot(&wm));
+        pdf
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 + (0.001 - 0.1) * self.clearcoat_gloss
+    }
+}
+
+fn lerp(a: &Point3D, b: &Point3D, t: f64) -> Point3D {
+    *a * (1.0 - t) + *b * t
+}
+
+// The color with its luminance normalized away.
+fn hue(color: &Point3D) -> Point3D {
+    let l = luminance(color);
+    if l > 0.0 { *color / l } else { Point3D::new(1.0, 1.0, 1.0) }
+}
+
+// Schlick's approximation of the Fresnel reflectance from its value f0 at normal incidence.
+fn schlick(f0: &Point3D, cos_theta: f64) -> Point3D {
+    lerp(f0, &Point3D::new(1.0, 1.0, 1.0), (1.0 - cos_theta).clamp(0.0, 1.0).powi(5))
+}
+
+fn schlick_scalar(f0: f64, cos_theta: f64) -> f64 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+// Berry's distribution (GTR with exponent 1), whose long tail gives the clear coat its haze.
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = 1.0 + (a2 - 1.0) * cos_h * cos_h;
+    (a2 - 1.0) / (PI * a2.ln() * t)
+}
+
+// A microfacet normal with density gtr1 times its cosine.
+fn sample_gtr1(alpha: f64) -> Point3D {
+    let a2 = alpha * alpha;
+    let cos_h = ((1.0 - a2.powf(1.0 - random_f64())) / (1.0 - a2)).sqrt();
+    let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+    let phi = 2.0 * PI * random_f64();
+    Point3D::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h)
+}
+
+// Smith masking for GGX, divided by twice the cosine so that the product of two carries the
+// 1 / (4 cos_i cos_o) of the microfacet BRDF.
+fn smith_ggx(cos_theta: f64, alpha: f64) -> f64 {
+    let (a, b) = (alpha * alpha, cos_theta * cos_theta);
+    1.0 / (cos_theta + (a + b - a * b).sqrt())
+}
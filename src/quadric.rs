@@ -0,0 +1,491 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use std::f64::consts::PI;
+
+// All quadrics stand on the xz plane of their local frame with the y axis as their axis of
+// symmetry, and are placed in the world by the center of their base.
+
+pub struct Cylinder {
+    center: Point3D,
+    radius: f64,
+    height: f64,
+    capped: bool, // Close the top and bottom with disks
+    material: Material,
+}
+
+pub struct Cone {
+    center: Point3D,
+    radius: f64, // Radius of the base; the apex is at the top
+    height: f64,
+    capped: bool, // Close the base with a disk
+    material: Material,
+}
+
+pub struct Paraboloid {
+    center: Point3D,
+    radius: f64, // Radius of the rim; the vertex is at the base
+    height: f64,
+    capped: bool, // Close the rim with a disk
+    material: Material,
+}
+
+pub struct Hyperboloid {
+    center: Point3D,
+    waist_radius: f64, // Radius halfway up, where the surface is narrowest
+    rim_radius: f64, // Radius at the top and bottom
+    height: f64,
+    capped: bool, // Close the top and bottom with disks
+    material: Material,
+}
+
+// A point where a ray crosses a surface, in the local frame of the surface.
+pub struct Crossing {
+    pub t: f64,
+    pub outward_normal: Point3D,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Point3D,
+    pub dpdv: Point3D,
+}
+
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        // The ray runs parallel to the axis of a degenerate direction: the equation is linear.
+        return if b.abs() < 1e-12 { Vec::new() } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+    // Avoid cancellation by never subtracting two values of similar size.
+    let q = if b < 0.0 { -0.5 * (b - discriminant.sqrt()) } else { -0.5 * (b + discriminant.sqrt()) };
+    let (t0, t1) = (q / a, if q != 0.0 { c / q } else { q / a });
+    if t0 < t1 { vec![t0, t1] } else { vec![t1, t0] }
+}
+
+// Angle around the y axis mapped to [0, 1], matching the u of Sphere.
+pub fn angle_u(p: &Point3D) -> f64 {
+    ((-p.z()).atan2(p.x()) + PI) / (2.0 * PI)
+}
+
+// How a point moves with the u of angle_u, around the y axis.
+pub fn around_axis(p: &Point3D) -> Point3D {
+    Point3D::new(p.z(), 0.0, -p.x()) * (2.0 * PI)
+}
+
+// Unit vector from the y axis out to p, or zero on the axis.
+pub fn radial(p: &Point3D) -> Point3D {
+    let outward = Point3D::new(p.x(), 0.0, p.z());
+    let length = outward.length();
+    if length > 0.0 { outward / length } else { Point3D::new(0.0, 0.0, 0.0) }
+}
+
+// Crossing of the disk of the given radius lying in the plane y = height of the local frame.
+fn cap_crossing(o: &Point3D, d: &Point3D, height: f64, radius: f64, facing_up: bool) -> Option<Crossing> {
+    if d.y() == 0.0 {
+        return None;
+    }
+    let t = (height - o.y()) / d.y();
+    let p = *o + *d * t;
+    let from_axis = (p.x() * p.x() + p.z() * p.z()).sqrt();
+    if from_axis > radius {
+        return None;
+    }
+    let outward_normal = Point3D::new(0.0, if facing_up { 1.0 } else { -1.0 }, 0.0);
+    Some(Crossing { t, outward_normal, u: angle_u(&p), v: from_axis / radius, dpdu: around_axis(&p), dpdv: radial(&p) * radius })
+}
+
+// Crossings of the curved side, given the roots of its implicit equation along the ray and the
+// gradient of that equation (which points outwards).
+fn side_crossings(o: &Point3D, d: &Point3D, roots: Vec<f64>, height: f64, gradient: impl Fn(&Point3D) -> Point3D) -> Vec<Crossing> {
+    roots.into_iter().filter_map(|t| {
+        let p = *o + *d * t;
+        if p.y() < 0.0 || p.y() > height {
+            return None;
+        }
+        let outward_normal = gradient(&p).unit_vector();
+        // Up the side in the plane through the axis, across the normal there.
+        let (out, up) = (radial(&p).dot(&outward_normal), outward_normal.y());
+        let dpdv = if out.abs() > 1e-12 {
+            (radial(&p) * (-up / out) + Point3D::new(0.0, 1.0, 0.0)) * height
+        } else {
+            Point3D::new(0.0, 0.0, 0.0)
+        };
+        Some(Crossing { t, outward_normal, u: angle_u(&p), v: p.y() / height, dpdu: around_axis(&p), dpdv })
+    }).collect()
+}
+
+fn to_record(r: &Ray, crossing: &Crossing, material: &Material) -> HitRecord {
+    let mut rec = HitRecord::new(r.at(crossing.t), crossing.outward_normal, crossing.t, true, material.clone(), crossing.u, crossing.v);
+    rec.set_tangents(crossing.dpdu, crossing.dpdv);
+    rec.set_face_normal(r, crossing.outward_normal);
+    rec
+}
+
+pub fn first_crossing(crossings: &[Crossing], r: &Ray, ray_tmin: f64, ray_tmax: f64, material: &Material) -> Option<HitRecord> {
+    crossings.iter()
+        .filter(|c| c.t > ray_tmin && c.t < ray_tmax)
+        .map(|c| to_record(r, c, material))
+        .filter(|rec| !rec.is_cut_out(r))
+        .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+// For closed surfaces, sorted crossings alternate between entering and leaving.
+pub fn crossing_spans(mut crossings: Vec<Crossing>, r: &Ray, material: &Material) -> Vec<Span> {
+    crossings.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(std::cmp::Ordering::Equal));
+    crossings.chunks_exact(2).map(|pair| {
+        let boundary = |c: &Crossing| {
+            let mut rec = HitRecord::new(r.at(c.t), c.outward_normal, c.t, true, material.clone(), c.u, c.v);
+            rec.set_tangents(c.dpdu, c.dpdv);
+            rec
+        };
+        Span::new(boundary(&pair[0]), boundary(&pair[1]))
+    }).collect()
+}
+
+fn local_box(center: Point3D, radius: f64, height: f64) -> Aabb {
+    Aabb::new(center + Point3D::new(-radius, 0.0, -radius), center + Point3D::new(radius, height, radius))
+}
+
+impl Cylinder {
+    pub fn new(center: Point3D, radius: f64, height: f64, capped: bool, material: Material) -> Cylinder {
+        Cylinder { center, radius, height, capped, material }
+    }
+
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        // x^2 + z^2 = radius^2
+        let a = d.x() * d.x() + d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z());
+        let c = o.x() * o.x() + o.z() * o.z() - self.radius * self.radius;
+        let roots = solve_quadratic(a, b, c);
+        let mut crossings = side_crossings(&o, &d, roots, self.height, |p| Point3D::new(p.x(), 0.0, p.z()));
+        if self.capped {
+            crossings.extend(cap_crossing(&o, &d, 0.0, self.radius, false));
+            crossings.extend(cap_crossing(&o, &d, self.height, self.radius, true));
+        }
+        crossings
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        first_crossing(&self.crossings(r), r, ray_tmin, ray_tmax, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        local_box(self.center, self.radius, self.height)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+impl Solid for Cylinder {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        // An open surface encloses no volume.
+        if !self.capped {
+            return Vec::new();
+        }
+        crossing_spans(self.crossings(r), r, &self.material)
+    }
+}
+
+impl Cone {
+    pub fn new(center: Point3D, radius: f64, height: f64, capped: bool, material: Material) -> Cone {
+        Cone { center, radius, height, capped, material }
+    }
+
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        // x^2 + z^2 = k^2 (height - y)^2, with k the slope of the side.
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let e = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z()) + 2.0 * k2 * e * d.y();
+        let c = o.x() * o.x() + o.z() * o.z() - k2 * e * e;
+        let roots = solve_quadratic(a, b, c);
+        let height = self.height;
+        let mut crossings = side_crossings(&o, &d, roots, self.height, |p| {
+            Point3D::new(p.x(), k2 * (height - p.y()), p.z())
+        });
+        if self.capped {
+            crossings.extend(cap_crossing(&o, &d, 0.0, self.radius, false));
+        }
+        crossings
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        first_crossing(&self.crossings(r), r, ray_tmin, ray_tmax, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        local_box(self.center, self.radius, self.height)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+impl Solid for Cone {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        if !self.capped {
+            return Vec::new();
+        }
+        crossing_spans(self.crossings(r), r, &self.material)
+    }
+}
+
+impl Paraboloid {
+    pub fn new(center: Point3D, radius: f64, height: f64, capped: bool, material: Material) -> Paraboloid {
+        Paraboloid { center, radius, height, capped, material }
+    }
+
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        // x^2 + z^2 = s y, with s chosen so the rim has the requested radius.
+        let s = self.radius * self.radius / self.height;
+        let a = d.x() * d.x() + d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z()) - s * d.y();
+        let c = o.x() * o.x() + o.z() * o.z() - s * o.y();
+        let roots = solve_quadratic(a, b, c);
+        let mut crossings = side_crossings(&o, &d, roots, self.height, |p| Point3D::new(2.0 * p.x(), -s, 2.0 * p.z()));
+        if self.capped {
+            crossings.extend(cap_crossing(&o, &d, self.height, self.radius, true));
+        }
+        crossings
+    }
+}
+
+impl Hittable for Paraboloid {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        first_crossing(&self.crossings(r), r, ray_tmin, ray_tmax, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        local_box(self.center, self.radius, self.height)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+impl Solid for Paraboloid {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        if !self.capped {
+            return Vec::new();
+        }
+        crossing_spans(self.crossings(r), r, &self.material)
+    }
+}
+
+impl Hyperboloid {
+    pub fn new(center: Point3D, waist_radius: f64, rim_radius: f64, height: f64, capped: bool, material: Material) -> Hyperboloid {
+        Hyperboloid { center, waist_radius, rim_radius, height, capped, material }
+    }
+
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        // x^2 + z^2 = waist^2 + c w^2, with w the height above the waist and c chosen so the
+        // top and bottom have the rim radius.
+        let half = self.height / 2.0;
+        let waist2 = self.waist_radius * self.waist_radius;
+        let curvature = (self.rim_radius * self.rim_radius - waist2) / (half * half);
+        let ow = o.y() - half;
+        let a = d.x() * d.x() + d.z() * d.z() - curvature * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z()) - 2.0 * curvature * ow * d.y();
+        let c = o.x() * o.x() + o.z() * o.z() - waist2 - curvature * ow * ow;
+        let roots = solve_quadratic(a, b, c);
+        let mut crossings = side_crossings(&o, &d, roots, self.height, |p| {
+            Point3D::new(p.x(), -curvature * (p.y() - half), p.z())
+        });
+        if self.capped {
+            crossings.extend(cap_crossing(&o, &d, 0.0, self.rim_radius, false));
+            crossings.extend(cap_crossing(&o, &d, self.height, self.rim_radius, true));
+        }
+        crossings
+    }
+}
+
+impl Hittable for Hyperboloid {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        first_crossing(&self.crossings(r), r, ray_tmin, ray_tmax, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let radius = self.rim_radius.max(self.waist_radius);
+        local_box(self.center, radius, self.height)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+impl Solid for Hyperboloid {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        if !self.capped {
+            return Vec::new();
+        }
+        crossing_spans(self.crossings(r), r, &self.material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::*;
+
+    fn material() -> Material {
+        Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))))
+    }
+
+    fn assert_near(a: Point3D, b: Point3D) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    // The t of the boundaries of each span, in order.
+    fn span_ts(spans: &[Span]) -> Vec<(f64, f64)> {
+        spans.iter().map(|span| (span.enter.t, span.exit.t)).collect()
+    }
+
+    #[test]
+    fn quadratic_roots_come_sorted() {
+        let roots = solve_quadratic(2.0, -2.0, -12.0);
+        assert_eq!(roots, vec![-2.0, 3.0]);
+        let roots = solve_quadratic(-1.0, 0.0, 4.0);
+        assert_eq!(roots, vec![-2.0, 2.0]);
+    }
+
+    #[test]
+    fn quadratic_without_real_roots() {
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn quadratic_with_a_double_root() {
+        let roots = solve_quadratic(1.0, -4.0, 4.0);
+        assert_eq!(roots.len(), 2);
+        assert!(roots.iter().all(|root| (root - 2.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn quadratic_that_is_linear() {
+        assert_eq!(solve_quadratic(0.0, 2.0, -3.0), vec![1.5]);
+        assert!(solve_quadratic(0.0, 0.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn quadratic_keeps_the_small_root_accurate() {
+        // Roots 1e-8 and 1e8: the textbook formula loses the small one to cancellation.
+        let roots = solve_quadratic(1.0, -(1e8 + 1e-8), 1.0);
+        assert!((roots[0] - 1e-8).abs() < 1e-20, "{:?}", roots);
+        assert!((roots[1] - 1e8).abs() < 1e-6, "{:?}", roots);
+    }
+
+    #[test]
+    fn cylinder_side_and_caps() {
+        let cylinder = Cylinder::new(Point3D::new(0.0, 0.0, 0.0), 1.0, 2.0, true, material());
+        let across = Ray::new(Point3D::new(-3.0, 1.0, 0.0), Point3D::new(1.0, 0.0, 0.0));
+        let rec = cylinder.hit(&across, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-9 && rec.front_face);
+        assert_near(rec.normal, Point3D::new(-1.0, 0.0, 0.0));
+        assert_eq!(span_ts(&cylinder.spans(&across)), vec![(2.0, 4.0)]);
+
+        let down = Ray::new(Point3D::new(0.5, 5.0, 0.0), Point3D::new(0.0, -1.0, 0.0));
+        let rec = cylinder.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_near(rec.normal, Point3D::new(0.0, 1.0, 0.0));
+        let spans = cylinder.spans(&down);
+        assert_eq!(span_ts(&spans), vec![(3.0, 5.0)]);
+        assert_near(spans[0].exit.normal, Point3D::new(0.0, -1.0, 0.0));
+
+        // From inside, the normal faces back against the ray.
+        let outwards = Ray::new(Point3D::new(0.0, 1.0, 0.0), Point3D::new(0.0, 0.0, 1.0));
+        let rec = cylinder.hit(&outwards, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9 && !rec.front_face);
+        assert_near(rec.normal, Point3D::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn open_cylinder_has_no_spans() {
+        let cylinder = Cylinder::new(Point3D::new(0.0, 0.0, 0.0), 1.0, 2.0, false, material());
+        let down = Ray::new(Point3D::new(0.5, 5.0, 0.0), Point3D::new(0.0, -1.0, 0.0));
+        assert!(cylinder.hit(&down, 0.001, f64::INFINITY).is_none());
+        let across = Ray::new(Point3D::new(-3.0, 1.0, 0.0), Point3D::new(1.0, 0.0, 0.0));
+        assert!(cylinder.hit(&across, 0.001, f64::INFINITY).is_some());
+        assert!(cylinder.spans(&across).is_empty());
+    }
+
+    #[test]
+    fn cone_side_leans_in_towards_the_apex() {
+        let cone = Cone::new(Point3D::new(0.0, 0.0, 0.0), 1.0, 2.0, true, material());
+        // Halfway up, the side is half the base radius out.
+        let across = Ray::new(Point3D::new(-3.0, 1.0, 0.0), Point3D::new(1.0, 0.0, 0.0));
+        let rec = cone.hit(&across, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-9);
+        assert_near(rec.normal, Point3D::new(-2.0, 1.0, 0.0).unit_vector());
+        let spans = cone.spans(&across);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].exit.t - 3.5).abs() < 1e-9);
+
+        let up = Ray::new(Point3D::new(0.5, -1.0, 0.0), Point3D::new(0.0, 1.0, 0.0));
+        let rec = cone.hit(&up, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert_near(rec.normal, Point3D::new(0.0, -1.0, 0.0));
+        let spans = cone.spans(&up);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].exit.t - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn paraboloid_rim_and_vertex() {
+        let paraboloid = Paraboloid::new(Point3D::new(0.0, 0.0, 0.0), 1.0, 1.0, true, material());
+        let down = Ray::new(Point3D::new(0.0, 3.0, 0.0), Point3D::new(0.0, -1.0, 0.0));
+        let rec = paraboloid.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-9);
+        assert_near(rec.normal, Point3D::new(0.0, 1.0, 0.0));
+        let spans = paraboloid.spans(&down);
+        assert_eq!(span_ts(&spans), vec![(2.0, 3.0)]);
+        assert_near(spans[0].exit.normal, Point3D::new(0.0, -1.0, 0.0));
+
+        // A quarter of the way up, the bowl is half the rim radius out.
+        let across = Ray::new(Point3D::new(-3.0, 0.25, 0.0), Point3D::new(1.0, 0.0, 0.0));
+        let rec = paraboloid.hit(&across, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-9);
+        assert_near(rec.normal, Point3D::new(-1.0, -1.0, 0.0).unit_vector());
+    }
+
+    #[test]
+    fn hyperboloid_waist_and_rims() {
+        let hyperboloid = Hyperboloid::new(Point3D::new(0.0, 0.0, 0.0), 0.5, 1.0, 2.0, true, material());
+        let across = Ray::new(Point3D::new(-3.0, 1.0, 0.0), Point3D::new(2.0, 0.0, 0.0));
+        let rec = hyperboloid.hit(&across, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.25).abs() < 1e-9);
+        assert_near(rec.p, Point3D::new(-0.5, 1.0, 0.0));
+        assert_near(rec.normal, Point3D::new(-1.0, 0.0, 0.0));
+        assert_eq!(span_ts(&hyperboloid.spans(&across)), vec![(1.25, 1.75)]);
+
+        // Up the axis, through both caps without touching the side.
+        let up = Ray::new(Point3D::new(0.0, -1.0, 0.0), Point3D::new(0.0, 1.0, 0.0));
+        let spans = hyperboloid.spans(&up);
+        assert_eq!(span_ts(&spans), vec![(1.0, 3.0)]);
+        assert_near(spans[0].enter.normal, Point3D::new(0.0, -1.0, 0.0));
+        assert_near(spans[0].exit.normal, Point3D::new(0.0, 1.0, 0.0));
+        let open = Hyperboloid::new(Point3D::new(0.0, 0.0, 0.0), 0.5, 1.0, 2.0, false, material());
+        assert!(open.hit(&up, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -1,13 +1,96 @@
 use rand::prelude::*;
+use std::cell::RefCell;
+
+thread_local! {
+    // While set, supplies every random number drawn on this thread in place of the thread's
+    // generator, letting a sampler such as Metropolis light transport control and replay each
+    // random decision of the code it runs.
+    static SAMPLE_SOURCE: RefCell<Option<Box<dyn FnMut() -> f64>>> = RefCell::new(None);
+}
 
 pub fn random_f64() -> f64 { // W: function `random_f64` is never used
+    if let Some(y) = SAMPLE_SOURCE.with(|source| source.borrow_mut().as_mut().map(|next| next())) {
+        return y;
+    }
     let mut rng = rand::thread_rng();
     let y: f64 = rng.gen::<f64>();
     y
 }
 
 pub fn random_in_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    let y: f64 = rng.gen_range(min..max);
-    y
+    min + (max - min) * random_f64()
+}
+
+// A number in [0, 1) that looks random but comes out the same whenever it is asked for the same
+// values, for random decisions that must not change when they are made again. It does not draw
+// from this thread's random numbers.
+pub fn hash_f64(values: &[f64]) -> f64 {
+    let mut hash: u64 = 0x9e3779b97f4a7c15;
+    for value in values.iter() {
+        // The SplitMix64 finalizer, mixing in one value at a time.
+        hash = (hash ^ value.to_bits()).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+    }
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Replaces the source of this thread's random numbers, in [0, 1), or restores the thread's
+// generator when given None.
+pub fn set_sample_source(source: Option<Box<dyn FnMut() -> f64>>) {
+    SAMPLE_SOURCE.with(|current| *current.borrow_mut() = source);
+}
+
+// Runs f with its random numbers drawn from source, then hands the thread back whatever source it
+// had before, so a sampler replaying this thread's numbers does not see the ones f used.
+pub fn with_sample_source<T>(source: Box<dyn FnMut() -> f64>, f: impl FnOnce() -> T) -> T {
+    let previous = SAMPLE_SOURCE.with(|current| current.borrow_mut().replace(source));
+    let result = f();
+    SAMPLE_SOURCE.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_the_same_for_the_same_values() {
+        let values = [0.25, -3.0, 1e9];
+        assert_eq!(hash_f64(&values), hash_f64(&values));
+        assert_eq!(hash_f64(&[]), hash_f64(&[]));
+    }
+
+    #[test]
+    fn hash_tells_values_and_their_order_apart() {
+        assert_ne!(hash_f64(&[1.0, 2.0]), hash_f64(&[2.0, 1.0]));
+        assert_ne!(hash_f64(&[1.0]), hash_f64(&[1.0, 0.0]));
+        assert_ne!(hash_f64(&[0.0]), hash_f64(&[-0.0]));
+    }
+
+    #[test]
+    fn hash_is_spread_over_the_unit_interval() {
+        let mut buckets = [0; 10];
+        for i in 0..10000 {
+            let hash = hash_f64(&[i as f64]);
+            assert!((0.0..1.0).contains(&hash), "{}", hash);
+            buckets[(hash * 10.0) as usize] += 1;
+        }
+        assert!(buckets.iter().all(|count| (800..1200).contains(count)), "{:?}", buckets);
+    }
+
+    #[test]
+    fn hash_does_not_draw_random_numbers() {
+        set_sample_source(Some(Box::new(|| panic!("hash_f64 drew a random number"))));
+        hash_f64(&[1.0, 2.0]);
+        set_sample_source(None);
+    }
+
+    #[test]
+    fn scoped_sources_restore_the_one_before() {
+        set_sample_source(Some(Box::new(|| 0.25)));
+        assert_eq!(with_sample_source(Box::new(|| 0.75), random_f64), 0.75);
+        assert_eq!(random_f64(), 0.25);
+        set_sample_source(None);
+    }
 }
@@ -0,0 +1,293 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::color::clamp;
+use crate::aabb::*;
+
+const MAX_STEPS: usize = 512; // Give up on rays that crawl along a surface without reaching it
+const HIT_EPSILON: f64 = 1e-5; // Distance from the surface that counts as a hit
+const NORMAL_EPSILON: f64 = 1e-5; // Offset used for the central difference normal estimate
+
+pub trait Sdf: Sync {
+    // Signed distance from p to the surface: negative inside, positive outside. Distance
+    // estimators that only bound the true distance from below also work, just more slowly.
+    fn distance(&self, p: Point3D) -> f64;
+}
+
+impl<F: Fn(Point3D) -> f64 + Sync> Sdf for F {
+    fn distance(&self, p: Point3D) -> f64 {
+        self(p)
+    }
+}
+
+// A shape given by a signed distance function has no surface coordinates: its hits have u = v = 0
+// and no tangents, so anisotropic conductors on it are brushed in an arbitrary direction, and
+// normal, bump and alpha maps are left off.
+pub struct SdfShape {
+    sdf: Box<dyn Sdf>,
+    center: Point3D, // Where the origin of the SDF is placed in the world
+    bound: f64, // Radius around center that contains the whole surface
+    material: Material,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Box<dyn Sdf>, center: Point3D, bound: f64, material: Material) -> SdfShape {
+        let material = if material.is_mapped() {
+            eprintln!("SDF shapes have no surface coordinates to map onto; leaving the maps off");
+            material.without_maps()
+        } else {
+            material
+        };
+        SdfShape { sdf, center, bound, material }
+    }
+
+    fn distance(&self, p: Point3D) -> f64 {
+        self.sdf.distance(p - self.center)
+    }
+
+    fn normal(&self, p: Point3D) -> Point3D {
+        // Gradient of the distance field by central differences.
+        let dx = Point3D::new(NORMAL_EPSILON, 0.0, 0.0);
+        let dy = Point3D::new(0.0, NORMAL_EPSILON, 0.0);
+        let dz = Point3D::new(0.0, 0.0, NORMAL_EPSILON);
+        Point3D::new(
+            self.distance(p + dx) - self.distance(p - dx),
+            self.distance(p + dy) - self.distance(p - dy),
+            self.distance(p + dz) - self.distance(p - dz),
+        ).unit_vector()
+    }
+
+    fn bounding_interval(&self, r: &Ray) -> Option<(f64, f64)> {
+        let oc = self.center - r.origin();
+        let a = r.direction().length_squared();
+        let h = r.direction().dot(&oc);
+        let c = oc.length_squared() - self.bound * self.bound;
+        let discriminant = h * h - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrtd = discriminant.sqrt();
+        Some(((h - sqrtd) / a, (h + sqrtd) / a))
+    }
+}
+
+impl Hittable for SdfShape {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        // Only march the part of the ray inside the bounding sphere.
+        let (t_enter, t_exit) = self.bounding_interval(r)?;
+        let t_start = if t_enter > ray_tmin { t_enter } else { ray_tmin };
+        let t_end = if t_exit < ray_tmax { t_exit } else { ray_tmax };
+        if t_start >= t_end {
+            return None;
+        }
+
+        // Sphere tracing: the distance to the surface is a step that can never overshoot it.
+        // Marching on |d| lets rays that start inside the shape find their way out. Only
+        // crossings out of the side the ray starts on count, so that rays leaving a surface they
+        // start on do not hit it again.
+        let dir_length = r.direction().length();
+        let inside = self.distance(r.at(ray_tmin)) < 0.0;
+        let mut t = t_start;
+        for _ in 0..MAX_STEPS {
+            let p = r.at(t);
+            let d = self.distance(p).abs();
+            if d < HIT_EPSILON {
+                let outward_normal = self.normal(p);
+                if (r.direction().dot(&outward_normal) > 0.0) == inside {
+                    let mut rec = HitRecord::new(p, Point3D::new(0.0, 0.0, 0.0), t, true, self.material.clone(), 0.0, 0.0);
+                    rec.set_face_normal(r, outward_normal);
+                    return Some(rec);
+                }
+            }
+            t += (if d > HIT_EPSILON { d } else { HIT_EPSILON }) / dir_length;
+            if t > t_end {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let extent = Point3D::new(self.bound, self.bound, self.bound);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+// Built-in distance functions. All are centered on the origin and are placed in the world by
+// SdfShape (or by Translated when combining several of them).
+
+pub struct RoundedBox {
+    pub half_extents: Point3D,
+    pub radius: f64, // Radius of the rounded edges, taken out of the half extents
+}
+
+pub struct TorusSdf {
+    pub major_radius: f64, // Distance from the center to the middle of the tube, in the xz plane
+    pub minor_radius: f64, // Radius of the tube
+}
+
+pub struct Capsule {
+    pub a: Point3D,
+    pub b: Point3D,
+    pub radius: f64,
+}
+
+pub struct MengerSponge {
+    pub half_size: f64,
+    pub iterations: usize,
+}
+
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: usize,
+    pub scale: f64, // The unscaled bulb fits in a radius of about 1.2
+}
+
+pub struct SmoothUnion {
+    pub a: Box<dyn Sdf>,
+    pub b: Box<dyn Sdf>,
+    pub k: f64, // Blending distance; 0 is a hard union
+}
+
+pub struct Translated {
+    pub sdf: Box<dyn Sdf>,
+    pub offset: Point3D,
+}
+
+fn abs(p: Point3D) -> Point3D {
+    Point3D::new(p.x().abs(), p.y().abs(), p.z().abs())
+}
+
+fn max_zero(p: Point3D) -> Point3D {
+    Point3D::new(p.x().max(0.0), p.y().max(0.0), p.z().max(0.0))
+}
+
+fn sd_box(p: Point3D, half_extents: Point3D) -> f64 {
+    let q = abs(p) - half_extents;
+    max_zero(q).length() + q.max_component().min(0.0)
+}
+
+impl Sdf for RoundedBox {
+    fn distance(&self, p: Point3D) -> f64 {
+        let r = Point3D::new(self.radius, self.radius, self.radius);
+        sd_box(p, self.half_extents - r) - self.radius
+    }
+}
+
+impl Sdf for TorusSdf {
+    fn distance(&self, p: Point3D) -> f64 {
+        let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (ring * ring + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+}
+
+impl Sdf for Capsule {
+    fn distance(&self, p: Point3D) -> f64 {
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let h = clamp(pa.dot(&ba) / ba.length_squared(), 0.0, 1.0);
+        (pa - ba * h).length() - self.radius
+    }
+}
+
+impl Sdf for MengerSponge {
+    fn distance(&self, p: Point3D) -> f64 {
+        // Carve the cross-shaped holes of every iteration out of a unit cube.
+        let p = p / self.half_size;
+        let mut d = sd_box(p, Point3D::new(1.0, 1.0, 1.0));
+        let mut s = 1.0;
+        for _ in 0..self.iterations {
+            let wrap = |x: f64| (x * s).rem_euclid(2.0) - 1.0;
+            let a = Point3D::new(wrap(p.x()), wrap(p.y()), wrap(p.z()));
+            s *= 3.0;
+            let r = abs(Point3D::new(1.0, 1.0, 1.0) - abs(a) * 3.0);
+            let da = r.x().max(r.y());
+            let db = r.y().max(r.z());
+            let dc = r.z().max(r.x());
+            let c = (da.min(db).min(dc) - 1.0) / s;
+            d = d.max(c);
+        }
+        d * self.half_size
+    }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: Point3D) -> f64 {
+        // Distance estimate from the running derivative of the power iteration.
+        let c = p / self.scale;
+        let mut z = c;
+        let mut dr = 1.0;
+        let mut r = z.length();
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2.0 || r == 0.0 {
+                break;
+            }
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Point3D::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * zr + c;
+        }
+        if r == 0.0 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Point3D) -> f64 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        if self.k <= 0.0 {
+            return d1.min(d2);
+        }
+        let h = clamp(0.5 + 0.5 * (d2 - d1) / self.k, 0.0, 1.0);
+        d2 * (1.0 - h) + d1 * h - self.k * h * (1.0 - h)
+    }
+}
+
+impl Sdf for Translated {
+    fn distance(&self, p: Point3D) -> f64 {
+        self.sdf.distance(p - self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::*;
+
+    // A unit ball whose bounding sphere is its own surface, so rays reach the surface as soon as
+    // they start marching.
+    fn ball() -> SdfShape {
+        let material = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))));
+        SdfShape::new(Box::new(|p: Point3D| p.length() - 1.0), Point3D::new(0.0, 0.0, 0.0), 1.0, material)
+    }
+
+    #[test]
+    fn rays_from_outside_hit_the_near_side() {
+        let r = Ray::new(Point3D::new(0.0, 0.0, 3.0), Point3D::new(0.0, 0.0, -1.0));
+        let rec = ball().hit(&r, 0.001, f64::MAX).expect("ray misses the ball");
+        assert!((rec.t - 2.0).abs() < 1e-4, "{}", rec.t);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn rays_leaving_the_surface_do_not_hit_it_again() {
+        let outwards = Ray::new(Point3D::new(0.0, 0.0, 1.0), Point3D::new(0.0, 0.0, 1.0));
+        assert!(ball().hit(&outwards, 0.001, f64::MAX).is_none());
+
+        // Refracted into the ball, the ray finds its way out at the far side.
+        let inwards = Ray::new(Point3D::new(0.0, 0.0, 1.0), Point3D::new(0.0, 0.0, -1.0));
+        let rec = ball().hit(&inwards, 0.001, f64::MAX).expect("ray does not leave the ball");
+        assert!((rec.t - 2.0).abs() < 1e-4, "{}", rec.t);
+        assert!(!rec.front_face);
+    }
+}
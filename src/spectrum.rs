@@ -0,0 +1,423 @@
+use crate::point3d::*;
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+// Spectral rendering with hero wavelength sampling (Wilkie et al. 2014). Each camera path
+// carries light at three wavelengths, a randomly picked hero and two more evenly spaced across the
+// visible range, so that the three channels of Point3D hold spectral values instead of red, green
+// and blue. Colors in the scene are turned into spectra as they are looked up, and the film turns
+// the three samples back into RGB.
+//
+// Materials see the wavelengths of the path being traced through a thread-local, the same way
+// random numbers can be redirected in random.rs, so that they need no extra parameter. Outside of
+// a spectral path everything stays in RGB.
+
+pub const MIN_WAVELENGTH: f64 = 360.0; // In nanometers
+pub const MAX_WAVELENGTH: f64 = 830.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+    terminated: bool, // Only the hero is still carried, by a wavelength-dependent refraction
+}
+
+thread_local! {
+    static WAVELENGTHS: Cell<Option<Wavelengths>> = const { Cell::new(None) };
+}
+
+impl Wavelengths {
+    // The hero at u in [0, 1) across the visible range, and its rotations by a third of it.
+    pub fn sample(u: f64) -> Wavelengths {
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        let lambda = [0.0, 1.0, 2.0].map(|i| MIN_WAVELENGTH + ((u + i / 3.0) % 1.0) * range);
+        Wavelengths { lambda, terminated: false }
+    }
+}
+
+// Sets the wavelengths of the path about to be traced on this thread, or goes back to RGB.
+pub fn set_wavelengths(wavelengths: Option<Wavelengths>) {
+    WAVELENGTHS.with(|current| current.set(wavelengths));
+}
+
+pub fn wavelengths() -> Option<Wavelengths> {
+    WAVELENGTHS.with(|current| current.get())
+}
+
+// For a refraction that bends each wavelength its own way, so that a path can only follow the
+// hero's. Returns the factor to weigh the path by: the other wavelengths are dropped, and the hero
+// now stands for all three.
+pub fn terminate_secondary() -> Point3D {
+    match wavelengths() {
+        Some(wavelengths) if !wavelengths.terminated => {
+            set_wavelengths(Some(Wavelengths { terminated: true, ..wavelengths }));
+            Point3D::new(3.0, 0.0, 0.0)
+        }
+        _ => Point3D::new(1.0, 1.0, 1.0),
+    }
+}
+
+// Whether the path being traced follows its hero wavelength alone.
+pub fn secondary_terminated() -> bool {
+    wavelengths().is_some_and(|wavelengths| wavelengths.terminated)
+}
+
+// Carries all wavelengths again, for tracing another subpath of the same sample.
+pub fn restore_secondary() {
+    if let Some(wavelengths) = wavelengths() {
+        set_wavelengths(Some(Wavelengths { terminated: false, ..wavelengths }));
+    }
+}
+
+// A linear sRGB color as the values of a smooth spectrum at the path's wavelengths, or as is
+// outside of spectral paths. The spectrum mixes three basis spectra, so colors of any brightness,
+// lights included, turn into spectra the same way; white is the constant spectrum.
+pub fn from_rgb(rgb: Point3D) -> Point3D {
+    match wavelengths() {
+        Some(wavelengths) => {
+            let value = |lambda: f64| spectrum_value(&rgb, lambda);
+            Point3D::new(value(wavelengths.lambda[0]), value(wavelengths.lambda[1]), value(wavelengths.lambda[2]))
+        }
+        None => rgb,
+    }
+}
+
+// The value at a wavelength of the spectrum from_rgb turns a color into.
+pub fn spectrum_value(rgb: &Point3D, lambda: f64) -> f64 {
+    let weights = tables().basis_weights.transform(rgb);
+    let basis = basis(lambda);
+    (weights.x() * basis.x() + weights.y() * basis.y() + weights.z() * basis.z()).max(0.0)
+}
+
+// A spectrum given by its value at each wavelength, at the path's wavelengths, or outside of
+// spectral paths as the linear sRGB of a surface reflecting that much of each wavelength.
+pub fn from_spectrum(spectrum: impl Fn(f64) -> f64) -> Point3D {
+    match wavelengths() {
+        Some(wavelengths) => Point3D::new(spectrum(wavelengths.lambda[0]), spectrum(wavelengths.lambda[1]), spectrum(wavelengths.lambda[2])),
+        None => {
+            let tables = tables();
+            let xyz = tables.coarse_xyz.iter().fold(Point3D::new(0.0, 0.0, 0.0), |xyz, (lambda, weight)| xyz + *weight * spectrum(*lambda));
+            tables.xyz_to_rgb.transform(&xyz)
+        }
+    }
+}
+
+// Linear sRGB of the light a spectral path found at its wavelengths, as an estimate over the
+// visible range with wavelengths picked uniformly. Light found outside of spectral paths is
+// already RGB.
+pub fn to_rgb(value: Point3D) -> Point3D {
+    match wavelengths() {
+        Some(wavelengths) => {
+            let values = [value.x(), value.y(), value.z()];
+            let xyz = wavelengths.lambda.iter().zip(values.iter()).fold(Point3D::new(0.0, 0.0, 0.0), |xyz, (lambda, value)| {
+                xyz + cie_xyz(*lambda) * *value
+            }) * ((MAX_WAVELENGTH - MIN_WAVELENGTH) / 3.0);
+            tables().xyz_to_rgb.transform(&xyz)
+        }
+        None => value,
+    }
+}
+
+// Refractive index at a wavelength in nanometers, from Cauchy's equation n = a + b / λ² or the
+// Sellmeier equation n² = 1 + Σ b λ² / (λ² - c), both with λ in micrometers as in glass catalogs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    None,
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub fn refraction_index(&self, lambda: f64) -> Option<f64> {
+        let l2 = (lambda / 1000.0).powi(2);
+        match self {
+            Dispersion::None => None,
+            Dispersion::Cauchy { a, b } => Some(a + b / l2),
+            Dispersion::Sellmeier { b, c } => Some((1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()),
+        }
+    }
+}
+
+// Light given by its value at each wavelength, for emitters. Values are spectral radiance, and
+// with scenes measured in meters a value of 1 is 1 W/(m²·sr·nm): the unit in which the renderer's
+// white 1 is the constant spectrum 1.
+//
+// Measured and black body spectra are white balanced for daylight when lighting the scene, as an
+// eye or a camera adapted to it would see them: divided by the D65 illuminant, which is scaled to
+// the luminance of white 1, so that daylight at 6500 K comes out white and candle light orange.
+// Like a camera's white balance this brightens or darkens other colors a little. Colors given as
+// RGB are balanced already.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spectrum {
+    Rgb(Point3D), // The smooth spectrum from_rgb turns a color into
+    Blackbody { temperature: f64, scale: f64 }, // Planck's law at a temperature in Kelvin, times scale
+    Tabulated(Vec<(f64, f64)>), // Values at increasing wavelengths in nanometers, linear in between and zero outside
+}
+
+impl Spectrum {
+    // The radiance of a black body, as a physical amount; normalized() gives just its color. None
+    // unless the temperature is above absolute zero.
+    pub fn blackbody(temperature: f64) -> Option<Spectrum> {
+        if !(temperature > 0.0 && temperature.is_finite()) {
+            return None;
+        }
+        Some(Spectrum::Blackbody { temperature, scale: 1.0 })
+    }
+
+    // None unless the wavelengths increase.
+    pub fn tabulated(samples: Vec<(f64, f64)>) -> Option<Spectrum> {
+        if !samples.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            return None;
+        }
+        Some(Spectrum::Tabulated(samples))
+    }
+
+    pub fn value(&self, lambda: f64) -> f64 {
+        match self {
+            Spectrum::Rgb(rgb) => spectrum_value(rgb, lambda),
+            Spectrum::Blackbody { temperature, scale } => {
+                const H: f64 = 6.62607015e-34; // Planck constant, J·s
+                const C: f64 = 299792458.0; // Speed of light, m/s
+                const K: f64 = 1.380649e-23; // Boltzmann constant, J/K
+                let l = lambda * 1e-9;
+                // Per meter of wavelength, so per nanometer after the last factor.
+                scale * 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * K * temperature)).exp() - 1.0)) * 1e-9
+            }
+            Spectrum::Tabulated(samples) => {
+                let next = samples.partition_point(|(l, _)| *l < lambda);
+                if next == 0 || next == samples.len() {
+                    return samples.get(next).filter(|(l, _)| *l == lambda).map_or(0.0, |(_, value)| *value);
+                }
+                let ((l0, v0), (l1, v1)) = (samples[next - 1], samples[next]);
+                v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+            }
+        }
+    }
+
+    // The value at a wavelength once white balanced for daylight, as the scene is lit by it.
+    pub fn balanced_value(&self, lambda: f64) -> f64 {
+        match self {
+            Spectrum::Rgb(rgb) => spectrum_value(rgb, lambda),
+            _ => self.value(lambda) / daylight(lambda),
+        }
+    }
+
+    // The same color, white balanced, at the luminance of white 1, to be scaled to taste. None for
+    // spectra without visible light, which have no color.
+    pub fn normalized(&self) -> Option<Spectrum> {
+        let luminance = self.balanced_xyz().y();
+        if luminance <= 0.0 {
+            return None;
+        }
+        Some(self.scaled(tables().y_integral / luminance))
+    }
+
+    pub fn scaled(&self, factor: f64) -> Spectrum {
+        match self {
+            Spectrum::Rgb(rgb) => Spectrum::Rgb(*rgb * factor),
+            Spectrum::Blackbody { temperature, scale } => Spectrum::Blackbody { temperature: *temperature, scale: scale * factor },
+            Spectrum::Tabulated(samples) => Spectrum::Tabulated(samples.iter().map(|(l, value)| (*l, value * factor)).collect()),
+        }
+    }
+
+    // Linear sRGB of the light once white balanced, summed by nanometer so that no narrow line
+    // of a tabulated spectrum is missed. Colors outside of the sRGB gamut, like the deep orange of
+    // a candle, are desaturated at the same luminance until no channel is negative.
+    pub fn rgb(&self) -> Point3D {
+        match self {
+            Spectrum::Rgb(rgb) => *rgb,
+            _ => {
+                let xyz = self.balanced_xyz();
+                let rgb = tables().xyz_to_rgb.transform(&xyz);
+                let gray = xyz.y() / tables().y_integral;
+                let lowest = rgb.x().min(rgb.y()).min(rgb.z());
+                if gray <= 0.0 {
+                    Point3D::new(0.0, 0.0, 0.0)
+                } else if lowest < 0.0 {
+                    let g = Point3D::new(gray, gray, gray);
+                    g + (rgb - g) * (gray / (gray - lowest))
+                } else {
+                    rgb
+                }
+            }
+        }
+    }
+
+    fn balanced_xyz(&self) -> Point3D {
+        integrate_visible(|lambda| cie_xyz(lambda) * self.balanced_value(lambda))
+    }
+
+    // Radiance over the visible range, in W/(m²·sr).
+    pub fn radiance(&self) -> f64 {
+        integrate_visible(|lambda| Point3D::new(1.0, 0.0, 0.0) * self.value(lambda)).x()
+    }
+
+    // Radiance weighted by the eye's sensitivity, 1/683 of the luminance in cd/m².
+    pub fn luminous_radiance(&self) -> f64 {
+        integrate_visible(|lambda| cie_xyz(lambda) * self.value(lambda)).y()
+    }
+}
+
+// A 3x3 matrix, by rows.
+#[derive(Debug, Clone, Copy)]
+struct Matrix([[f64; 3]; 3]);
+
+impl Matrix {
+    fn transform(&self, v: &Point3D) -> Point3D {
+        let row = |r: &[f64; 3]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z();
+        Point3D::new(row(&self.0[0]), row(&self.0[1]), row(&self.0[2]))
+    }
+
+    fn inverse(&self) -> Matrix {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+        let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2) + m[0][2] * cofactor(1, 2, 0, 1);
+        Matrix([
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ].map(|row| row.map(|c| c / determinant)))
+    }
+}
+
+// Conversions derived once from the color matching functions.
+struct Tables {
+    xyz_to_rgb: Matrix, // White balanced, so that the constant spectrum 1 is white
+    basis_weights: Matrix, // Linear sRGB to the weights of the basis spectra
+    coarse_xyz: Vec<(f64, Point3D)>, // Wavelengths 20 nm apart and their weights towards XYZ
+    y_integral: f64, // Of the luminous efficiency, in nanometers
+    daylight_scale: f64, // Brings D65 to the luminance of the constant spectrum 1
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let y_integral = integrate_visible(cie_xyz).y();
+
+        // XYZ to linear sRGB, then scaled by channel so that the equal energy white is (1, 1, 1).
+        let srgb = Matrix([[3.2404542, -1.5371385, -0.4985314], [-0.9692660, 1.8760108, 0.0415560], [0.0556434, -0.2040259, 1.0572252]]);
+        let white = srgb.transform(&(integrate_visible(cie_xyz) / y_integral));
+        let scale = [white.x(), white.y(), white.z()];
+        let xyz_to_rgb = Matrix(srgb.0.iter().zip(scale.iter()).map(|(row, s)| row.map(|c| c / (s * y_integral))).collect::<Vec<_>>().try_into().unwrap());
+
+        // Colors of the basis spectra; mixing them by the inverse gives back any color.
+        let colors: Vec<Point3D> = (0..3).map(|k| xyz_to_rgb.transform(&integrate_visible(|lambda| cie_xyz(lambda) * basis(lambda).axis(k)))).collect();
+        let mix = Matrix([0, 1, 2].map(|row| [colors[0].axis(row), colors[1].axis(row), colors[2].axis(row)]));
+
+        // Steps of 20 nm are fine enough for the smooth spectra passed to from_spectrum, which
+        // include the interference of films up to about a micrometer thick.
+        let step = 20.0;
+        let steps = (MAX_WAVELENGTH - MIN_WAVELENGTH) as usize;
+        let coarse_xyz = (0..steps / 20).map(|i| MIN_WAVELENGTH + (i as f64 + 0.5) * step).map(|lambda| (lambda, cie_xyz(lambda) * step)).collect();
+        let daylight_scale = y_integral / integrate_visible(|lambda| cie_xyz(lambda) * d65(lambda)).y();
+        Tables { xyz_to_rgb, basis_weights: mix.inverse(), coarse_xyz, y_integral, daylight_scale }
+    })
+}
+
+// Relative spectral power of the CIE D65 illuminant, 10 nm apart from MIN_WAVELENGTH.
+const D65: [f64; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0, 96.3342, 95.788, 88.6856,
+    90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.349,
+    61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054, 63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+fn d65(lambda: f64) -> f64 {
+    let x = ((lambda - MIN_WAVELENGTH) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let i = (x as usize).min(D65.len() - 2);
+    D65[i] + (D65[i + 1] - D65[i]) * (x - i as f64)
+}
+
+// D65 at the luminance of white 1, the light that white balanced spectra are divided by.
+fn daylight(lambda: f64) -> f64 {
+    d65(lambda) * tables().daylight_scale
+}
+
+// Sum over the visible range in steps of one nanometer.
+fn integrate_visible(f: impl Fn(f64) -> Point3D) -> Point3D {
+    let steps = (MAX_WAVELENGTH - MIN_WAVELENGTH) as usize;
+    (0..steps).map(|i| f(MIN_WAVELENGTH + i as f64 + 0.5)).fold(Point3D::new(0.0, 0.0, 0.0), |sum, v| sum + v)
+}
+
+// Three smooth spectra covering the long, middle and short wavelengths, which add up to one.
+fn basis(lambda: f64) -> Point3D {
+    let smoothstep = |edge: f64, width: f64| {
+        let t = ((lambda - edge) / width + 0.5).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    let red = smoothstep(590.0, 40.0);
+    let not_blue = smoothstep(490.0, 40.0);
+    Point3D::new(red, not_blue - red, 1.0 - not_blue)
+}
+
+// CIE 1931 color matching functions, in the multi-lobe Gaussian fit of Wyman et al. 2013.
+fn cie_xyz(lambda: f64) -> Point3D {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma_below } else { sigma_above };
+        (-0.5 * t * t).exp()
+    };
+    Point3D::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The RGB the film makes of a color turned into a spectrum, averaged over evenly spread
+    // hero wavelengths.
+    fn round_trip(rgb: Point3D) -> Point3D {
+        let steps = 3000;
+        let sum = (0..steps).fold(Point3D::new(0.0, 0.0, 0.0), |sum, i| {
+            set_wavelengths(Some(Wavelengths::sample((i as f64 + 0.5) / steps as f64)));
+            sum + to_rgb(from_rgb(rgb))
+        });
+        set_wavelengths(None);
+        sum / steps as f64
+    }
+
+    #[test]
+    fn colors_survive_the_round_trip_through_a_spectrum() {
+        let colors = [
+            Point3D::new(1.0, 1.0, 1.0),
+            Point3D::new(0.5, 0.5, 0.5),
+            Point3D::new(0.8, 0.3, 0.1),
+            Point3D::new(0.1, 0.6, 0.2),
+            Point3D::new(0.2, 0.3, 0.9),
+            Point3D::new(4.0, 3.0, 2.0),
+        ];
+        for rgb in colors.iter() {
+            let back = round_trip(*rgb);
+            assert!((back - *rgb).length() < 0.01 * rgb.length(), "{:?} came back as {:?}", rgb, back);
+        }
+    }
+
+    #[test]
+    fn colors_stay_rgb_outside_of_spectral_paths() {
+        set_wavelengths(None);
+        let rgb = Point3D::new(0.8, 0.3, 0.1);
+        assert_eq!(from_rgb(rgb), rgb);
+        assert_eq!(to_rgb(rgb), rgb);
+    }
+
+    #[test]
+    fn cauchy_and_sellmeier_refractive_indices() {
+        assert_eq!(Dispersion::None.refraction_index(550.0), None);
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.01 };
+        assert!((cauchy.refraction_index(500.0).unwrap() - 1.54).abs() < 1e-12);
+        // Glass bends blue more than red.
+        let sellmeier = Dispersion::Sellmeier { b: [1.0, 0.2, 1.0], c: [0.006, 0.02, 100.0] };
+        assert!(sellmeier.refraction_index(450.0).unwrap() > sellmeier.refraction_index(650.0).unwrap());
+    }
+
+    #[test]
+    fn unusable_spectra_are_refused() {
+        assert_eq!(Spectrum::blackbody(0.0), None);
+        assert_eq!(Spectrum::blackbody(-300.0), None);
+        assert_eq!(Spectrum::tabulated(vec![(500.0, 1.0), (450.0, 1.0)]), None);
+        assert_eq!(Spectrum::tabulated(Vec::new()).and_then(|spectrum| spectrum.normalized()), None);
+        assert!(Spectrum::blackbody(2700.0).and_then(|spectrum| spectrum.normalized()).is_some());
+    }
+}
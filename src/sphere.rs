@@ -2,6 +2,10 @@ use crate::point3d::*;
 use crate::ray::*;
 use crate::hittable::*;
 use crate::material::*;
+use crate::aabb::*;
+use crate::random::*;
+use crate::onb::*;
+use std::f64::consts::PI;
 
 pub struct Sphere {
     center: Point3D,
@@ -11,34 +15,114 @@ pub struct Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> { 
-        let oc = self.center - r.origin();
-        let a = r.direction().length_squared();
-        let b = r.direction().dot(&oc) * -2.0 ;
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = b * b - 4.0 * a * c;
-    
-        if discriminant >= 0.0 {
-            let sqrtd = discriminant.sqrt();
-            let root_near = ((-b) - sqrtd) / (2.0 * a);
-            let root_far = ((-b) + sqrtd) / (2.0 * a);
+        if let Some((root_near, root_far)) = self.roots(r) {
             for root in [root_near, root_far].iter() {
                 if *root < ray_tmax && *root > ray_tmin {
                     let p = r.at(*root);
                     let normal = (p - self.center) / self.radius;
                     let front_face = r.direction().dot(&normal) < 0.0;
-                    return Some(HitRecord::new(
+                    let (u, v) = Sphere::get_sphere_uv(&normal);
+                    let mut rec = HitRecord::new(
                         p,
                         if front_face { normal } else { -normal },
                         *root,
                         front_face,
-                        self.material,
-                    ));
+                        self.material.clone(),
+                        u,
+                        v,
+                    );
+                    if rec.is_cut_out(r) {
+                        continue;
+                    }
+                    let (dpdu, dpdv) = self.tangents(&normal);
+                    rec.set_tangents(dpdu, dpdv);
+                    return Some(rec);
                 }
             }
         }
         None
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let extent = Point3D::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+
+    fn pdf_value(&self, origin: &Point3D, direction: &Point3D) -> f64 {
+        // Directions are sampled uniformly from the cone the sphere subtends, or from the whole
+        // sphere of directions when the origin is inside it.
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let cos_theta = direction.unit_vector().dot(&to_center.unit_vector());
+        if cos_theta < cos_theta_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    fn random(&self, origin: &Point3D) -> Point3D {
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return Point3D::random_unit_vector();
+        }
+
+        // Uniform direction within the cone, around the z axis...
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + random_f64() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * random_f64();
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        // ...then turned so the z axis points at the center.
+        let uvw = Onb::new(&to_center);
+        uvw.transform(&Point3D::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord> {
+        let normal = Point3D::random_unit_vector();
+        let (u, v) = Sphere::get_sphere_uv(&normal);
+        Some(HitRecord::new(self.center + normal * self.radius, normal, 0.0, true, self.material.clone(), u, v))
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        if self.material.is_emissive() {
+            lights.push(self);
+        }
+    }
+}
+
+impl Solid for Sphere {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        match self.roots(r) {
+            Some((root_near, root_far)) => {
+                let boundary = |t: f64, front_face: bool| {
+                    let p = r.at(t);
+                    let normal = (p - self.center) / self.radius;
+                    let (u, v) = Sphere::get_sphere_uv(&normal);
+                    let mut rec = HitRecord::new(p, normal, t, front_face, self.material.clone(), u, v);
+                    let (dpdu, dpdv) = self.tangents(&normal);
+                    rec.set_tangents(dpdu, dpdv);
+                    rec
+                };
+                vec![Span::new(boundary(root_near, true), boundary(root_far, false))]
+            }
+            None => Vec::new(),
+        }
+    }
 }
 
 impl Sphere {
@@ -46,4 +130,42 @@ impl Sphere {
         let r = if radius < 0.0 { 0.0 } else { radius };
         Sphere{ center, radius: r, material }
     }
+
+    fn get_sphere_uv(p: &Point3D) -> (f64, f64) {
+        // p: a given point on the sphere of radius one, centered at the origin.
+        // u: returned value [0,1] of angle around the Y axis from X=-1.
+        // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // Derivatives of the point on the sphere with the normal given, by the u and v of
+    // get_sphere_uv. Both vanish at the poles, where u is undefined.
+    fn tangents(&self, normal: &Point3D) -> (Point3D, Point3D) {
+        let (x, y, z) = (normal.x(), normal.y(), normal.z());
+        let sin_theta = (x * x + z * z).sqrt();
+        if sin_theta < 1e-9 {
+            return (Point3D::new(0.0, 0.0, 0.0), Point3D::new(0.0, 0.0, 0.0));
+        }
+        let dpdu = Point3D::new(z, 0.0, -x) * (2.0 * PI * self.radius);
+        let dpdv = Point3D::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta) * (PI * self.radius);
+        (dpdu, dpdv)
+    }
+
+    fn roots(&self, r: &Ray) -> Option<(f64, f64)> {
+        let oc = self.center - r.origin();
+        let a = r.direction().length_squared();
+        let b = r.direction().dot(&oc) * -2.0 ;
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrtd = discriminant.sqrt();
+        let root_near = ((-b) - sqrtd) / (2.0 * a);
+        let root_far = ((-b) + sqrtd) / (2.0 * a);
+        Some((root_near, root_far))
+    }
 }
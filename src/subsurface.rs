@@ -0,0 +1,319 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use crate::random::*;
+use crate::spectrum::*;
+use crate::microfacet::*;
+use crate::onb::*;
+use std::f64::consts::PI;
+
+// Light that travels below the surface of skin, wax, marble or milk before it leaves again. A
+// Translucent object is a closed boundary filled with a medium that scatters and absorbs light,
+// through which paths walk at random (as in Chiang et al. 2016): the object reports a hit inside
+// itself wherever a path scatters off the medium, at a distance picked by how dense the medium is,
+// so the integrators follow light through it the way they follow it between any other surfaces.
+//
+// Scattering inside counts as a specular bounce, as a shadow ray from there could never reach a
+// light through the boundary; lights are sampled where the path leaves through the boundary.
+// BDPT leaves the walks to camera subpaths: light subpaths stop where they enter an object.
+//
+// Distances are sampled by the density of one channel, picked in proportion to the light each
+// channel still carries on the walk so far, which keeps channels that die out from dragging
+// the others along. The integrators carry the walk from the scatter that draws the distance to
+// its next event to the ray that follows it, and find where that ray scatters with hit_on_walk,
+// so that the object does not draw random numbers itself. Rays without a walk, like those of the
+// AOVs, cross the medium without scattering.
+#[derive(Debug, Clone, Copy)]
+pub struct Medium {
+    pub albedo: Point3D, // Color of the object as a whole, once light has scattered around inside
+    pub mean_free_path: Point3D, // Average distance light of each channel travels between events
+    channels: Point3D, // Proportions the walk reached the hit with
+    arrival: Point3D, // Light reaching the hit from the last event, over the chance of getting there
+}
+
+// The state of a random walk through a medium, as carried from one event to the next.
+#[derive(Debug, Clone, Copy)]
+pub struct Walk {
+    channels: Point3D, // Chance of sampling by each channel, adding up to one
+    distance: f64, // To the next event
+}
+
+impl Walk {
+    // A walk carrying light in the given proportions, with the distance to its next event drawn
+    // by the density of a channel picked by them.
+    fn start(medium: &Medium, light: Point3D) -> Walk {
+        let total = light.x() + light.y() + light.z();
+        let channels = if total > 0.0 && total.is_finite() { light / total } else { Point3D::new(1.0, 1.0, 1.0) / 3.0 };
+        let u = random_f64();
+        let channel = if u < channels.x() { 0 } else if u < channels.x() + channels.y() { 1 } else { 2 };
+        Walk { channels, distance: -(1.0 - random_f64()).ln() / medium.density().axis(channel) }
+    }
+}
+
+impl Medium {
+    pub fn new(albedo: Point3D, mean_free_path: Point3D) -> Medium {
+        Medium { albedo, mean_free_path, channels: Point3D::new(1.0, 1.0, 1.0) / 3.0, arrival: Point3D::new(1.0, 1.0, 1.0) }
+    }
+
+    // The medium at the event a walk reached.
+    fn at_event(&self, walk: &Walk) -> Medium {
+        Medium { channels: walk.channels, arrival: self.event_weight(walk), ..*self }
+    }
+
+    // The medium where a ray that crossed distance inside leaves through the boundary.
+    fn at_boundary(&self, walk: Option<&Walk>, distance: f64) -> Medium {
+        match walk {
+            Some(walk) => Medium { channels: walk.channels, arrival: self.escape_weight(walk, distance), ..*self },
+            None => Medium { arrival: self.transmittance(distance), ..*self },
+        }
+    }
+
+    // Chance of being scattered rather than absorbed at an event, per channel, that brings out
+    // the albedo in a thick slab of the medium (the fit of Chiang et al. 2016).
+    fn single_scattering_albedo(&self) -> Point3D {
+        let albedo = from_rgb(self.albedo);
+        let invert = |a: f64| {
+            let a = a.clamp(0.0, 1.0);
+            1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+        };
+        Point3D::new(invert(albedo.x()), invert(albedo.y()), invert(albedo.z()))
+    }
+
+    // Events per unit distance, per channel.
+    fn density(&self) -> Point3D {
+        let mean_free_path = from_rgb(self.mean_free_path);
+        Point3D::new(1.0 / mean_free_path.x().max(1e-6), 1.0 / mean_free_path.y().max(1e-6), 1.0 / mean_free_path.z().max(1e-6))
+    }
+
+    fn transmittance(&self, distance: f64) -> Point3D {
+        let density = self.density();
+        Point3D::new((-density.x() * distance).exp(), (-density.y() * distance).exp(), (-density.z() * distance).exp())
+    }
+
+    // Light arriving at the event a walk sampled, relative to the density of sampling it there:
+    // the density of events times the transmittance, by channel, over the same mixed by the
+    // proportions.
+    fn event_weight(&self, walk: &Walk) -> Point3D {
+        let density_transmittance = self.density() * self.transmittance(walk.distance);
+        let pdf = density_transmittance.dot(&walk.channels);
+        if pdf <= 0.0 {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        density_transmittance / pdf
+    }
+
+    // Light reaching the boundary at distance without an event, relative to the chance of that.
+    fn escape_weight(&self, walk: &Walk, distance: f64) -> Point3D {
+        let transmittance = self.transmittance(distance);
+        let probability = transmittance.dot(&walk.channels);
+        if probability <= 0.0 {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        transmittance / probability
+    }
+
+    // Scatters equally in all directions, and walks on.
+    pub fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterSample> {
+        let f = self.single_scattering_albedo() * self.arrival;
+        let mut sample = ScatterSample::new(Point3D::random_unit_vector(), f, 1.0, true);
+        sample.walk = Some(Walk::start(self, self.channels * f));
+        Some(sample)
+    }
+}
+
+// The boundary of a Translucent object. From outside it reflects like a smooth or rough
+// dielectric and lets the rest of the light into the medium in no particular direction, the
+// walk inside forgetting where it came from anyway. From inside, light leaves diffusely, by the
+// Fresnel transmittance towards where it leaves to, and what cannot leave is mirrored back in.
+// Behind the boundary, light has crossed the medium without being scattered.
+#[derive(Debug, Clone, Copy)]
+pub struct Subsurface {
+    pub refraction_index: f64, // Of the inside over the outside
+    pub distribution: TrowbridgeReitz,
+    pub medium: Medium,
+    exitance: f64, // Fraction of the light from inside that leaves
+}
+
+impl Subsurface {
+    pub fn new(albedo: Point3D, mean_free_path: Point3D, refraction_index: f64, roughness: f64) -> Subsurface {
+        let alpha = roughness_to_alpha(roughness);
+        let mut subsurface = Subsurface { refraction_index, distribution: TrowbridgeReitz::new(alpha, alpha), medium: Medium::new(albedo, mean_free_path), exitance: 0.0 };
+        subsurface.exitance = subsurface.exitance();
+        subsurface
+    }
+
+    // Light reaching a hit on the inside of the boundary has crossed the medium from the last
+    // event.
+    fn weight(&self, rec: &HitRecord) -> Point3D {
+        if rec.front_face { Point3D::new(1.0, 1.0, 1.0) } else { self.medium.arrival }
+    }
+
+    // Diffuse transmission between a direction outside, at cos_outside from the normal, and any
+    // direction inside.
+    fn transmission(&self, cos_outside: f64) -> f64 {
+        (1.0 - fresnel_dielectric(cos_outside, self.refraction_index)) / PI
+    }
+
+    // Fraction of the light from inside that leaves: the transmission over all directions
+    // outside, by cosine. Worked out once, in new.
+    fn exitance(&self) -> f64 {
+        let steps = 32;
+        (0..steps).map(|i| {
+            let cos_theta = (i as f64 + 0.5) / steps as f64;
+            self.transmission(cos_theta) * cos_theta * 2.0 * PI / steps as f64
+        }).sum()
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
+        let uvw = Onb::new(&rec.normal);
+        let wo = uvw.local(&-r_in.direction().unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let mut sample = if rec.front_face {
+            // Reflect with the chance of the Fresnel reflectance, or else enter.
+            let reflectance = fresnel_dielectric(wo.z(), self.refraction_index);
+            if random_f64() >= reflectance {
+                let wi = -uvw.transform(&Point3D::random_cosine_direction());
+                let wo = uvw.transform(&wo);
+                ScatterSample::new(wi, self.eval(rec, &wi, &wo), self.pdf(rec, &wi, &wo), false)
+            } else if self.distribution.effectively_smooth() {
+                let wi = Point3D::new(-wo.x(), -wo.y(), wo.z());
+                ScatterSample::new(uvw.transform(&wi), Point3D::new(1.0, 1.0, 1.0) * reflectance, reflectance, true)
+            } else {
+                let wi = reflect_local(&wo, &self.distribution.sample_wm(&wo));
+                if wi.z() <= 0.0 {
+                    return None;
+                }
+                let (wi, wo) = (uvw.transform(&wi), uvw.transform(&wo));
+                ScatterSample::new(wi, self.eval(rec, &wi, &wo), self.pdf(rec, &wi, &wo), false)
+            }
+        } else {
+            // Leave with the chance of the exitance, or else be mirrored back in.
+            let exitance = self.exitance;
+            if random_f64() < exitance {
+                let wi = -uvw.transform(&Point3D::random_cosine_direction());
+                let wo = uvw.transform(&wo);
+                ScatterSample::new(wi, self.eval(rec, &wi, &wo), self.pdf(rec, &wi, &wo), false)
+            } else {
+                let wi = Point3D::new(-wo.x(), -wo.y(), wo.z());
+                ScatterSample::new(uvw.transform(&wi), self.weight(rec) * (1.0 - exitance), 1.0 - exitance, true)
+            }
+        };
+
+        // A walk starts with light entering the object, and goes on after light is mirrored
+        // back in. The normal faces the side the ray came from.
+        let crosses = sample.direction.dot(&rec.normal) < 0.0;
+        if rec.front_face && crosses {
+            sample.walk = Some(Walk::start(&self.medium, Point3D::new(1.0, 1.0, 1.0)));
+        } else if !rec.front_face && !crosses {
+            sample.walk = Some(Walk::start(&self.medium, self.medium.channels * sample.weight(&rec.normal)));
+        }
+        Some(sample)
+    }
+
+    pub fn eval(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> Point3D {
+        let uvw = Onb::new(&rec.normal);
+        let (wi, wo) = (uvw.local(wi), uvw.local(wo));
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Point3D::new(0.0, 0.0, 0.0);
+        }
+        let f = match (rec.front_face, wi.z() > 0.0) {
+            (true, true) if !self.distribution.effectively_smooth() => {
+                let wm = (wi + wo).unit_vector();
+                let reflectance = fresnel_dielectric(wo.dot(&wm), self.refraction_index);
+                self.distribution.d(&wm) * self.distribution.g(&wo, &wi) * reflectance / (4.0 * wi.z() * wo.z())
+            }
+            (true, false) => self.transmission(wo.z()),
+            (false, false) => self.transmission(-wi.z()),
+            _ => 0.0,
+        };
+        self.weight(rec) * f
+    }
+
+    pub fn pdf(&self, rec: &HitRecord, wi: &Point3D, wo: &Point3D) -> f64 {
+        let uvw = Onb::new(&rec.normal);
+        let (wi, wo) = (uvw.local(wi), uvw.local(wo));
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return 0.0;
+        }
+        match (rec.front_face, wi.z() > 0.0) {
+            (true, true) if !self.distribution.effectively_smooth() => {
+                let wm = (wi + wo).unit_vector();
+                fresnel_dielectric(wo.z(), self.refraction_index) * self.distribution.d_visible(&wo, &wm) / (4.0 * wo.dot(&wm))
+            }
+            (true, false) => (1.0 - fresnel_dielectric(wo.z(), self.refraction_index)) * -wi.z() / PI,
+            (false, false) => self.exitance * -wi.z() / PI,
+            _ => 0.0,
+        }
+    }
+}
+
+// A closed object filled with a scattering medium. The material of the boundary object itself
+// is replaced by the Subsurface one.
+pub struct Translucent {
+    boundary: Box<dyn Hittable>,
+    material: Subsurface,
+}
+
+impl Translucent {
+    pub fn new(boundary: Box<dyn Hittable>, material: Subsurface) -> Translucent {
+        Translucent { boundary, material }
+    }
+}
+
+impl Hittable for Translucent {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        // Holes cut into the boundary's own material are skipped by the boundary.
+        let mut rec = self.boundary.hit(r, ray_tmin, ray_tmax)?;
+        let mut material = self.material;
+        if !rec.front_face {
+            // The ray crossed the medium without scattering, as rays that are not on a walk do.
+            material.medium = material.medium.at_boundary(None, (rec.t - ray_tmin) * r.direction().length());
+        }
+        rec.material = Material::Subsurface(material);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(Material::Subsurface(self.material));
+    }
+}
+
+// What r reaches in world, when it continues walk through a translucent object: the next event
+// of the walk inside the medium, or else the boundary where the walk leaves. Integrators follow
+// the rays of their paths with this in place of world.hit.
+pub fn hit_on_walk(world: &dyn Hittable, r: &Ray, walk: Option<&Walk>, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+    let walk = match walk {
+        Some(walk) => walk,
+        None => return world.hit(r, ray_tmin, ray_tmax),
+    };
+    // The boundary beyond ray_tmax still tells whether the event comes first.
+    let mut rec = world.hit(r, ray_tmin, f64::MAX)?;
+    if let Material::Subsurface(mut material) = rec.material.clone() {
+        if !rec.front_face {
+            // Distances are counted from ray_tmin, which keeps rays from hitting where they start.
+            let length = r.direction().length();
+            let t = ray_tmin + walk.distance / length;
+            if t < rec.t {
+                if t >= ray_tmax {
+                    return None;
+                }
+                let normal = -r.direction().unit_vector();
+                return Some(HitRecord::new(r.at(t), normal, t, true, Material::Medium(material.medium.at_event(walk)), rec.u, rec.v));
+            }
+            material.medium = material.medium.at_boundary(Some(walk), (rec.t - ray_tmin) * length);
+            rec.material = Material::Subsurface(material);
+        }
+    }
+    if rec.t >= ray_tmax {
+        return None;
+    }
+    Some(rec)
+}
@@ -0,0 +1,120 @@
+use crate::point3d::*;
+use crate::spectrum::*;
+use crate::random::*;
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Texture {
+    SolidColor(Point3D),
+    Checker(Checker),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Checker {
+    pub scale: f64, // Number of squares along each unit of u and v
+    pub even: Point3D,
+    pub odd: Point3D,
+}
+
+impl Texture {
+    pub fn value(&self, u: f64, v: f64, p: &Point3D) -> Point3D {
+        let color = match self {
+            Texture::SolidColor(color) => *color,
+            Texture::Checker(c) => c.value(u, v, p),
+        };
+        from_rgb(color)
+    }
+}
+
+impl Checker {
+    pub fn new(scale: f64, even: Point3D, odd: Point3D) -> Checker {
+        Checker { scale, even, odd }
+    }
+
+    fn value(&self, u: f64, v: f64, _p: &Point3D) -> Point3D {
+        let u_square = (u * self.scale).floor() as i64;
+        let v_square = (v * self.scale).floor() as i64;
+        if (u_square + v_square) % 2 == 0 { self.even } else { self.odd }
+    }
+}
+
+// A grid of values over u and v, like the pixels of an image, kept as they are rather than as
+// colors: normals, heights and other data that only happen to be stored as images. Lookups wrap
+// around, so the grid tiles the surface.
+#[derive(Clone)]
+pub struct ImageMap {
+    values: Vec<Point3D>, // Row-major, from the top row, at v = 1
+    width: usize,
+    height: usize,
+    checksum: f64, // Of the values, which tells maps apart in place of printing them all
+}
+
+impl fmt::Debug for ImageMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImageMap").field("width", &self.width).field("height", &self.height).field("checksum", &self.checksum).finish()
+    }
+}
+
+impl ImageMap {
+    pub fn new(values: Vec<Point3D>, width: usize, height: usize) -> ImageMap {
+        assert!(width >= 1 && height >= 1 && values.len() == width * height, "image map needs width * height values");
+        let checksum = hash_f64(&values.iter().flat_map(|value| [value.x(), value.y(), value.z()]).collect::<Vec<f64>>());
+        ImageMap { values, width, height, checksum }
+    }
+
+    // Reads a PNG, scaling the full range of its bit depth (8 or 16 bits) to [0, 1]. Gray images
+    // fill all three channels, and alpha is dropped.
+    pub fn from_png(path: &str) -> io::Result<ImageMap> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        let sample = |i: usize| match info.bit_depth {
+            png::BitDepth::Sixteen => u16::from_be_bytes([buf[i * 2], buf[i * 2 + 1]]) as f64 / 65535.0,
+            _ => buf[i] as f64 / 255.0,
+        };
+        let values = (0..width * height).map(|k| {
+            let first = k * channels;
+            if channels < 3 {
+                Point3D::new(1.0, 1.0, 1.0) * sample(first)
+            } else {
+                Point3D::new(sample(first), sample(first + 1), sample(first + 2))
+            }
+        }).collect();
+        Ok(ImageMap::new(values, width, height))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // The value in column i and row j, counted from the top left.
+    pub fn texel(&self, i: usize, j: usize) -> Point3D {
+        self.values[j * self.width + i]
+    }
+
+    // Bilinear interpolation between the four values around u, v, each value sitting at the
+    // center of its pixel.
+    pub fn value(&self, u: f64, v: f64) -> Point3D {
+        let x = u.rem_euclid(1.0) * self.width as f64 - 0.5;
+        let y = (1.0 - v).rem_euclid(1.0) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let wrap = |c: f64, size: usize| (c as i64).rem_euclid(size as i64) as usize;
+        let (i0, i1) = (wrap(x0, self.width), wrap(x0 + 1.0, self.width));
+        let (j0, j1) = (wrap(y0, self.height), wrap(y0 + 1.0, self.height));
+        let top = self.texel(i0, j0) * (1.0 - fx) + self.texel(i1, j0) * fx;
+        let bottom = self.texel(i0, j1) * (1.0 - fx) + self.texel(i1, j1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
@@ -0,0 +1,120 @@
+use crate::point3d::*;
+use crate::spectrum::*;
+use std::f64::consts::PI;
+
+// A transparent film a few hundred nanometers thick on a surface, like soap, oil on water or the
+// oxide on heated metal. Light reflected off the top of the film and light reflected off the
+// surface beneath interfere, cancelling some wavelengths and reinforcing others depending on the
+// thickness and the angle, which gives the colors their sheen. The reflectance is the Airy sum of
+// all the bounces inside the film, computed by wavelength for each polarization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThinFilm {
+    pub thickness: f64, // In nanometers
+    pub refraction_index: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, refraction_index: f64) -> ThinFilm {
+        ThinFilm { thickness, refraction_index }
+    }
+
+    // Reflectance for light arriving at cos_theta from the normal through a medium of refractive
+    // index outside, onto the film over a surface of complex refractive index substrate(λ).
+    pub fn reflectance(&self, cos_theta: f64, outside: f64, substrate: impl Fn(f64) -> (f64, f64)) -> Point3D {
+        from_spectrum(|lambda| {
+            let (eta, k) = substrate(lambda);
+            self.reflectance_at(cos_theta, lambda, outside, Complex::new(eta, k))
+        })
+    }
+
+    fn reflectance_at(&self, cos_theta: f64, lambda: f64, outside: f64, substrate: Complex) -> f64 {
+        let n1 = Complex::new(outside, 0.0);
+        let n2 = Complex::new(self.refraction_index, 0.0);
+        // Snell's law holds across all layers, with complex cosines past a total internal
+        // reflection, where the light tunnels through the film as a decaying wave.
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt() * outside;
+        let cosine = |n: Complex| (Complex::new(1.0, 0.0) - (Complex::new(sin_theta, 0.0) / n).square()).sqrt();
+        let (cos1, cos2, cos3) = (Complex::new(cos_theta.clamp(0.0, 1.0), 0.0), cosine(n2), cosine(substrate));
+
+        // Phase gained by a round trip through the film.
+        let delta = n2 * cos2 * Complex::new(4.0 * PI * self.thickness / lambda, 0.0);
+        let phase = (Complex::new(0.0, 1.0) * delta).exp();
+
+        let airy = |r12: Complex, r23: Complex| {
+            let r = (r12 + r23 * phase) / (Complex::new(1.0, 0.0) + r12 * r23 * phase);
+            r.norm_squared()
+        };
+        let s = |na: Complex, ca: Complex, nb: Complex, cb: Complex| (na * ca - nb * cb) / (na * ca + nb * cb);
+        let p = |na: Complex, ca: Complex, nb: Complex, cb: Complex| (nb * ca - na * cb) / (nb * ca + na * cb);
+        let rs = airy(s(n1, cos1, n2, cos2), s(n2, cos2, substrate, cos3));
+        let rp = airy(p(n1, cos1, n2, cos2), p(n2, cos2, substrate, cos3));
+        ((rs + rp) / 2.0).min(1.0)
+    }
+}
+
+// Just enough complex arithmetic for the Fresnel amplitudes.
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    fn norm_squared(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn square(self) -> Complex {
+        self * self
+    }
+
+    // The principal root, with a non-negative real part.
+    fn sqrt(self) -> Complex {
+        let norm = self.norm_squared().sqrt();
+        let re = ((norm + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((norm - self.re) / 2.0).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Complex {
+        let magnitude = self.re.exp();
+        Complex::new(magnitude * self.im.cos(), magnitude * self.im.sin())
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.norm_squared();
+        Complex::new((self.re * other.re + self.im * other.im) / denominator, (self.im * other.re - self.re * other.im) / denominator)
+    }
+}
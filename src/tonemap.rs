@@ -0,0 +1,103 @@
+use crate::point3d::*;
+use crate::color::*;
+
+// Curves that compress the unbounded linear light of a render into the [0, 1] a display can
+// show. Each maps linear sRGB to linear sRGB; the sRGB transfer function is applied afterwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    Clamp, // No compression: everything brighter than 1 clips
+    Reinhard, // c / (1 + c) on each channel
+    ExtendedReinhard { white: f64 }, // Reinhard on luminance, reaching 1 at the white luminance
+    Hable, // John Hable's filmic curve from Uncharted 2
+    Aces, // Stephen Hill's fit of the ACES reference and sRGB output transforms
+    Agx, // Troy Sobotka's AgX, in the polynomial approximation of Benjamin Wrensch
+}
+
+impl ToneMap {
+    pub fn all() -> Vec<ToneMap> {
+        vec![ToneMap::Clamp, ToneMap::Reinhard, ToneMap::ExtendedReinhard { white: 4.0 }, ToneMap::Hable, ToneMap::Aces, ToneMap::Agx]
+    }
+
+    // A curve by its name, with the white luminance of extended-reinhard optionally given after
+    // a colon, as in extended-reinhard:8.
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        if let Some(white) = name.strip_prefix("extended-reinhard:") {
+            return match white.parse::<f64>() {
+                Ok(white) if white > 0.0 && white.is_finite() => Some(ToneMap::ExtendedReinhard { white }),
+                _ => None,
+            };
+        }
+        ToneMap::all().into_iter().find(|tone_map| tone_map.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMap::Clamp => "clamp",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::ExtendedReinhard { .. } => "extended-reinhard",
+            ToneMap::Hable => "hable",
+            ToneMap::Aces => "aces",
+            ToneMap::Agx => "agx",
+        }
+    }
+
+    pub fn apply(&self, color: Point3D) -> Point3D {
+        let color = Point3D::new(color.x().max(0.0), color.y().max(0.0), color.z().max(0.0));
+        match self {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => Point3D::new(color.x() / (1.0 + color.x()), color.y() / (1.0 + color.y()), color.z() / (1.0 + color.z())),
+            ToneMap::ExtendedReinhard { white } => {
+                let l = luminance(&color);
+                if l <= 0.0 {
+                    return color;
+                }
+                color * ((1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMap::Hable => {
+                // The curve is normalized so that the linear white point maps to 1.
+                let exposure_bias = 2.0;
+                let white = 11.2;
+                let scale = 1.0 / hable(white);
+                Point3D::new(hable(color.x() * exposure_bias), hable(color.y() * exposure_bias), hable(color.z() * exposure_bias)) * scale
+            }
+            ToneMap::Aces => {
+                // Into the ACES working space, through the fitted curve, and back to sRGB.
+                let input = [[0.59719, 0.35458, 0.04823], [0.07600, 0.90834, 0.01566], [0.02840, 0.13383, 0.83777]];
+                let output = [[1.60475, -0.53108, -0.07367], [-0.10208, 1.10813, -0.00605], [-0.00327, -0.07276, 1.07602]];
+                let fit = |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081);
+                let c = transform(&input, color);
+                transform(&output, Point3D::new(fit(c.x()), fit(c.y()), fit(c.z())))
+            }
+            ToneMap::Agx => {
+                // Inset towards the achromatic axis, so that bright saturated colors desaturate as
+                // they approach white instead of clipping into a flat hue.
+                let inset = [[0.842479062253094, 0.0784335999999992, 0.0792237451477643], [0.0423282422610123, 0.878468636469772, 0.0791661274605434], [0.0423756549057051, 0.0784336, 0.879142973793104]];
+                let outset = [[1.19687900512017, -0.0980208811401368, -0.0990297440797205], [-0.0528968517574562, 1.15190312990417, -0.0989611768448433], [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]];
+                let (min_ev, max_ev) = (-12.47393, 4.026069);
+                let c = transform(&inset, color);
+                let encode = |v: f64| agx_contrast(((v.max(1e-10).log2() - min_ev) / (max_ev - min_ev)).clamp(0.0, 1.0));
+                // The curve's output is display encoded with a 2.2 gamma; undo that to stay linear.
+                let c = transform(&outset, Point3D::new(encode(c.x()), encode(c.y()), encode(c.z())));
+                Point3D::new(c.x().max(0.0).powf(2.2), c.y().max(0.0).powf(2.2), c.z().max(0.0).powf(2.2))
+            }
+        }
+    }
+}
+
+// Rows of the matrix times color.
+fn transform(matrix: &[[f64; 3]; 3], color: Point3D) -> Point3D {
+    let row = |r: &[f64; 3]| r[0] * color.x() + r[1] * color.y() + r[2] * color.z();
+    Point3D::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+// AgX's sigmoid over log-encoded exposure in [0, 1].
+fn agx_contrast(x: f64) -> f64 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+}
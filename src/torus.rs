@@ -0,0 +1,280 @@
+use crate::point3d::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use crate::quadric::*;
+use std::f64::consts::PI;
+
+// A ring around the y axis of its local frame, lying in the xz plane through its center.
+pub struct Torus {
+    center: Point3D,
+    major_radius: f64, // Distance from the center to the middle of the tube
+    minor_radius: f64, // Radius of the tube
+    material: Material,
+}
+
+const EQN_EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EQN_EPSILON
+}
+
+// Real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3 = 0 (Cardano).
+fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+
+    // Substitute x = y - a/3 to eliminate the quadratic term: y^3 + 3p y + 2q = 0.
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let mut roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // Three real roots.
+        let phi = (-q / (-cb_p).sqrt()).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![t * phi.cos(), -t * (phi + PI / 3.0).cos(), -t * (phi - PI / 3.0).cos()]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    for root in roots.iter_mut() {
+        *root -= a / 3.0;
+    }
+    roots
+}
+
+// Real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3 + c[4] x^4 = 0 (Ferrari).
+fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    // Substitute x = y - a/4 to eliminate the cubic term: y^4 + p y^2 + q y + r = 0.
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+    let mut roots = if is_zero(r) {
+        // No absolute term: y (y^3 + p y + q) = 0.
+        let mut roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // Take one root of the resolvent cubic to split the quartic into two quadratics.
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) { 0.0 } else if u > 0.0 { u.sqrt() } else { return Vec::new() };
+        let v = if is_zero(v) { 0.0 } else if v > 0.0 { v.sqrt() } else { return Vec::new() };
+        let mut roots = solve_quadratic(1.0, if q < 0.0 { -v } else { v }, z - u);
+        roots.extend(solve_quadratic(1.0, if q < 0.0 { v } else { -v }, z + u));
+        roots
+    };
+
+    for root in roots.iter_mut() {
+        *root -= a / 4.0;
+    }
+    roots
+}
+
+impl Torus {
+    pub fn new(center: Point3D, major_radius: f64, minor_radius: f64, material: Material) -> Torus {
+        Torus { center, major_radius, minor_radius, material }
+    }
+
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        // Solve in units of a normalized direction, starting from the point of the ray closest
+        // to the center. Keeping the coefficients small is what makes the quartic solvable in
+        // double precision.
+        let dir_length = r.direction().length();
+        let d = r.direction() / dir_length;
+        let shift = -(r.origin() - self.center).dot(&d);
+        let o = r.origin() - self.center + d * shift;
+
+        let major2 = self.major_radius * self.major_radius;
+        let minor2 = self.minor_radius * self.minor_radius;
+        let e = o.length_squared() - major2 - minor2;
+        let f = o.dot(&d);
+        let coefficients = [
+            e * e - 4.0 * major2 * (minor2 - o.y() * o.y()),
+            4.0 * f * e + 8.0 * major2 * o.y() * d.y(),
+            2.0 * e + 4.0 * f * f + 4.0 * major2 * d.y() * d.y(),
+            4.0 * f,
+            1.0,
+        ];
+        let polynomial = |s: f64| coefficients.iter().rev().fold(0.0, |acc, c| acc * s + c);
+        let derivative = |s: f64| {
+            4.0 * s * s * s * coefficients[4] + 3.0 * s * s * coefficients[3] + 2.0 * s * coefficients[2] + coefficients[1]
+        };
+
+        solve_quartic(coefficients).into_iter().map(|s| {
+            // Polish the closed-form root with a couple of Newton steps.
+            let mut s = s;
+            for _ in 0..2 {
+                let slope = derivative(s);
+                if slope != 0.0 {
+                    s -= polynomial(s) / slope;
+                }
+            }
+            let p = o + d * s;
+            let ring = Point3D::new(p.x(), 0.0, p.z()).unit_vector() * self.major_radius;
+            let outward_normal = (p - ring).unit_vector();
+            let from_ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+            let tube_angle = p.y().atan2(from_ring);
+            Crossing {
+                t: (s + shift) / dir_length,
+                outward_normal,
+                u: angle_u(&p),
+                v: (tube_angle + PI) / (2.0 * PI),
+                dpdu: around_axis(&p),
+                // Around the tube, in the plane through the axis.
+                dpdv: (radial(&p) * -p.y() + Point3D::new(0.0, from_ring, 0.0)) * (2.0 * PI),
+            }
+        }).collect()
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        // Missing the box is much cheaper than solving the quartic.
+        if !self.bounding_box().hit(r, ray_tmin, ray_tmax) {
+            return None;
+        }
+        first_crossing(&self.crossings(r), r, ray_tmin, ray_tmax, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Point3D::new(outer, self.minor_radius, outer);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+
+    fn collect_materials(&self, materials: &mut Vec<Material>) {
+        materials.push(self.material.clone());
+    }
+}
+
+impl Solid for Torus {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        crossing_spans(self.crossings(r), r, &self.material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::*;
+
+    // Coefficients, lowest power first, of the monic polynomial with the given roots.
+    fn from_roots(roots: &[f64]) -> Vec<f64> {
+        roots.iter().fold(vec![1.0], |c, root| {
+            let mut next = vec![0.0; c.len() + 1];
+            for (i, value) in c.iter().enumerate() {
+                next[i + 1] += value;
+                next[i] -= value * root;
+            }
+            next
+        })
+    }
+
+    fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(f64::total_cmp);
+        roots
+    }
+
+    fn torus() -> Torus {
+        let material = Material::Lambertian(Lambertian::new(Texture::SolidColor(Point3D::new(0.5, 0.5, 0.5))));
+        Torus::new(Point3D::new(0.0, 0.0, 0.0), 1.0, 0.25, material)
+    }
+
+    // Distance from p to the surface of the torus around the origin.
+    fn off_surface(torus: &Torus, p: &Point3D) -> f64 {
+        let from_ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - torus.major_radius;
+        ((from_ring * from_ring + p.y() * p.y()).sqrt() - torus.minor_radius).abs()
+    }
+
+    #[test]
+    fn quartic_finds_distinct_roots() {
+        let expected = [-3.0, -1.0, 2.0, 4.0];
+        let c = from_roots(&expected);
+        let roots = sorted(solve_quartic([c[0], c[1], c[2], c[3], c[4]]));
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        // (x² + 1)(x² + 4)
+        assert!(solve_quartic([4.0, 0.0, 5.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn quartic_keeps_roots_close_to_touching() {
+        let expected = [-2.0, 1.0, 1.0 + 1e-4, 3.0];
+        let c = from_roots(&expected);
+        let roots = sorted(solve_quartic([c[0], c[1], c[2], c[3], c[4]]));
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            assert!((root - expected).abs() < 1e-6, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn cubic_finds_single_and_triple_real_roots() {
+        let roots = sorted(solve_cubic([6.0, -5.0, -2.0, 1.0]));
+        assert_eq!(roots.len(), 3);
+        for (root, expected) in roots.iter().zip([-2.0, 1.0, 3.0].iter()) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+        // (x - 2)(x² + 1)
+        let roots = solve_cubic([-2.0, 1.0, -2.0, 1.0]);
+        assert_eq!(roots.len(), 1);
+        assert!((roots[0] - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rays_grazing_the_top_of_the_tube() {
+        let torus = torus();
+        let dir = Point3D::new(1.0, 0.0, 0.0);
+        // Just below the top, the ray crosses the tube on both sides of the hole.
+        let r = Ray::new(Point3D::new(-3.0, torus.minor_radius - 1e-6, 0.0), dir);
+        let crossings = torus.crossings(&r);
+        assert_eq!(crossings.len(), 4);
+        for crossing in crossings.iter() {
+            assert!(off_surface(&torus, &r.at(crossing.t)) < 1e-9);
+        }
+        // Just above it, it misses.
+        let r = Ray::new(Point3D::new(-3.0, torus.minor_radius + 1e-6, 0.0), dir);
+        assert!(torus.crossings(&r).is_empty());
+    }
+
+    #[test]
+    fn rays_through_the_hole_and_across_the_tube() {
+        let torus = torus();
+        // Down through the hole, parallel to the axis.
+        let r = Ray::new(Point3D::new(0.0, 3.0, 0.0), Point3D::new(0.0, -2.0, 0.0));
+        assert!(torus.crossings(&r).is_empty());
+        // Down through the tube, with a direction that is not a unit vector.
+        let r = Ray::new(Point3D::new(1.0, 3.0, 0.0), Point3D::new(0.0, -2.0, 0.0));
+        let ts = sorted(torus.crossings(&r).iter().map(|crossing| crossing.t).collect());
+        assert_eq!(ts.len(), 2);
+        assert!((ts[0] - 1.375).abs() < 1e-9 && (ts[1] - 1.625).abs() < 1e-9, "{:?}", ts);
+    }
+}